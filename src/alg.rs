@@ -1,8 +1,12 @@
 pub mod result;
 pub use result::*;
 
+pub mod semivalue;
+pub use semivalue::*;
+
 pub mod join;
 pub mod permutation;
 pub mod proposed;
+pub mod stats;
 pub mod subset_utility;
 pub mod traditional;
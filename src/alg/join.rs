@@ -1,28 +1,65 @@
-use crate::{JoinPlan, ROW_ID_COL_NAME};
+use crate::{JoinKind, JoinPlan, ROW_ID_COL_NAME};
 use anyhow::{Context, Result};
 use polars::prelude::*;
 
-pub fn join<'a, 'b>(
-    df_fn: impl Fn(&'a str) -> Option<&'a DataFrame>,
-    plan: &'b JoinPlan,
+pub fn join<'a>(
+    df_fn: impl Fn(&str) -> Option<&'a DataFrame>,
+    plan: &JoinPlan,
 ) -> Result<DataFrame> {
-    let mut table = DataFrame::default();
-    let init_table = df_fn(plan.init_table).context("cannot find init table")?;
+    let mut table = df_fn(&plan.init_table)
+        .context("cannot find init table")?
+        .clone();
 
-    for (i, step) in plan.steps.iter().enumerate() {
-        let left_table = if i == 0 { init_table } else { &table };
-        let right_table = df_fn(step.table_to_join).context("cannot find table to join")?;
-        table = left_table.join(
-            right_table,
-            &step.left_join_keys,
-            &step.right_join_keys,
-            JoinType::Inner,
-            Some(format!(":{}", step.table_to_join)),
-        )?;
-
-        for (l, r) in step.left_join_keys.iter().zip(step.right_join_keys.iter()) {
-            table.rename(*l, *r)?;
-        }
+    let (steps, _) = plan.ordered_steps(|table_name| {
+        df_fn(table_name).map(|df| {
+            df.get_column_names()
+                .into_iter()
+                .map(|c| c.to_string())
+                .collect()
+        })
+    })?;
+    for (step, left_join_keys) in steps {
+        let right_table = df_fn(&step.table_to_join).context("cannot find table to join")?;
+        let suffix = Some(format!(":{}", step.table_to_join));
+        table = match step.join_type {
+            JoinKind::Inner => table.join(
+                right_table,
+                &left_join_keys,
+                &step.right_join_keys,
+                JoinType::Inner,
+                suffix,
+            )?,
+            JoinKind::Left => table.join(
+                right_table,
+                &left_join_keys,
+                &step.right_join_keys,
+                JoinType::Left,
+                suffix,
+            )?,
+            JoinKind::Outer => table.join(
+                right_table,
+                &left_join_keys,
+                &step.right_join_keys,
+                JoinType::Outer,
+                suffix,
+            )?,
+            JoinKind::Semi => {
+                // keep one row per matching row id, so that every match stays a witness.
+                let mut columns: Vec<&str> =
+                    step.right_join_keys.iter().map(|k| k.as_str()).collect();
+                if right_table.column(ROW_ID_COL_NAME).is_ok() {
+                    columns.push(ROW_ID_COL_NAME);
+                }
+                let right_table = right_table.select(columns)?.drop_duplicates(false, None)?;
+                table.join(
+                    &right_table,
+                    &left_join_keys,
+                    &step.right_join_keys,
+                    JoinType::Inner,
+                    suffix,
+                )?
+            }
+        };
     }
 
     table
@@ -34,31 +71,120 @@ pub fn join<'a, 'b>(
 
     table.drop_duplicates(false, None)?;
 
+    if let Some(filter) = &plan.filter {
+        table = table.filter(&filter.eval_mask(&table)?)?;
+    }
+
     Ok(table)
 }
 
+/// Apply the projection of `plan` to a join result, giving the answer tuples of the query.
+pub fn answer(df: &DataFrame, plan: &JoinPlan) -> Result<DataFrame> {
+    match &plan.projection {
+        Some(projection) => Ok(df
+            .select(projection.iter().map(|c| c.as_str()).collect::<Vec<_>>())?
+            .drop_duplicates(false, None)?),
+        None => Ok(df.clone()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{utils::test_data_dir, DataSet, PLANS};
+    use crate::{get_plan, utils::load_world, JoinStep};
 
     #[test]
     fn test_join() {
         polars_core::POOL.install(|| {
-            let data_dir = test_data_dir();
-            let world = DataSet::load(
-                "world",
-                data_dir.join("world"),
-                data_dir.join("world-metadata"),
+            let world = load_world();
+            let r = join(
+                |table_name| world.tables.get(table_name).map(|t| &t.df),
+                &get_plan("world").unwrap(),
             )
             .unwrap();
+            assert_eq!(r.shape().0, 30670);
+            for t in ["city", "country", "countrylanguage"] {
+                assert_eq!(
+                    r.column(&format!("{}:{}", ROW_ID_COL_NAME, t))
+                        .unwrap()
+                        .null_count(),
+                    0
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_join_star() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            // countrylanguage is listed before the country table it hangs off.
+            let plan = JoinPlan::new(
+                "city",
+                vec![
+                    JoinStep::new("countrylanguage", &["Code"], &["CountryCode"])
+                        .with_left_table("country"),
+                    JoinStep::new("country", &["CountryCode"], &["Code"]).with_left_table("city"),
+                ],
+            );
             let r = join(
                 |table_name| world.tables.get(table_name).map(|t| &t.df),
-                &PLANS["world"],
+                &plan,
             )
             .unwrap();
             assert_eq!(r.shape().0, 30670);
-            dbg!(r);
+            for t in plan.tables() {
+                r.column(&format!("{}:{}", ROW_ID_COL_NAME, t)).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_join_type() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let df_fn = |table_name: &str| world.tables.get(table_name).map(|t| &t.df);
+            let plan = |join_type| {
+                JoinPlan::new(
+                    "country",
+                    vec![JoinStep::new("city", &["Code"], &["CountryCode"])
+                        .with_join_type(join_type)],
+                )
+            };
+            let inner = join(df_fn, &plan(JoinKind::Inner)).unwrap();
+            let left = join(df_fn, &plan(JoinKind::Left)).unwrap();
+            let semi = join(df_fn, &plan(JoinKind::Semi)).unwrap();
+            let city_row_id = format!("{}:city", ROW_ID_COL_NAME);
+
+            // countries without cities are kept, null-extended.
+            assert!(left.shape().0 > inner.shape().0);
+            assert_eq!(
+                left.shape().0 - inner.shape().0,
+                left.column(&city_row_id).unwrap().null_count()
+            );
+            // one row per matching city, but no city columns.
+            assert_eq!(semi.shape().0, inner.shape().0);
+            assert!(semi.column("Name:city").is_err());
+        });
+    }
+
+    #[test]
+    fn test_answer() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let plan = get_plan("world")
+                .unwrap()
+                .with_filter("IsOfficial == 'T'".parse().unwrap())
+                .with_projection(&["Language"]);
+            let r = join(
+                |table_name| world.tables.get(table_name).map(|t| &t.df),
+                &plan,
+            )
+            .unwrap();
+            assert!(r.shape().0 < 30670);
+            let a = answer(&r, &plan).unwrap();
+            assert_eq!(a.shape().1, 1);
+            assert!(a.shape().0 < r.shape().0);
         });
     }
 }
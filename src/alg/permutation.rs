@@ -1,84 +1,550 @@
 use crate::{
-    alg::subset_utility::subset_utility_with_cache, utils::merge_sv, DataSet, SellerId, SellerSet,
-    ShapleyResult,
+    alg::{stats::SampleStats, subset_utility::subset_utility_with_cache},
+    utils::merge_sv,
+    DataSet, SellerId, SellerSet, SellerStructure, Semivalue, ShapleyResult, Utility,
 };
-use anyhow::Result;
+use anyhow::{bail, ensure, Error, Result};
 use dashmap::DashMap;
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
-use std::{collections::HashMap, time::Instant};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
-pub fn permutation_scheme(dataset: &DataSet, sample_size: usize) -> Result<ShapleyResult> {
-    info!("permutation scheme...");
-    let begin = Instant::now();
-    let cache: DashMap<SellerSet, f64> = DashMap::new();
-    let cache_ref = &cache;
+/// Random stream of sample `i`. Each sample owns a stream keyed on its index, so the drawn
+/// permutations do not depend on which thread draws them.
+pub(crate) fn sample_rng(seed: u64, i: usize) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(i as u64);
+    rng
+}
 
-    let mut shapley_values = (0..sample_size)
-        .into_par_iter()
-        .map(|i| {
-            info!("sample #{}", i);
-            let mut rng = thread_rng();
-            let mut sellers: Vec<SellerId> = dataset.sellers.iter().copied().collect();
-            sellers.shuffle(&mut rng);
-
-            let mut last_utility = 0.;
-            let mut seller_set = SellerSet::default();
-            let mut ans = HashMap::new();
-
-            for seller in sellers {
-                seller_set.insert(seller);
-                let subset_utility =
-                    subset_utility_with_cache(dataset, seller_set.clone(), cache_ref)?;
-                ans.insert(seller, subset_utility - last_utility);
-                last_utility = subset_utility;
-            }
+/// Stopping rule of the adaptive permutation scheme.
+#[derive(Debug, Clone)]
+pub struct Convergence {
+    /// Stop once the standard error of every seller's running mean is at most `tolerance`.
+    pub tolerance: f64,
+    /// Stop once this much time has passed.
+    pub time_budget: Option<Duration>,
+    /// Never stop before drawing this many samples, as the variance estimate of few samples
+    /// is unreliable.
+    pub min_sample_size: usize,
+    pub max_sample_size: Option<usize>,
+}
 
-            info!("sample #{} done", i);
-            Ok(ans)
+impl Convergence {
+    fn is_done(&self, stats: &SampleStats, elapsed: Duration) -> bool {
+        let n = stats.count();
+        self.max_sample_size.is_some_and(|max| n >= max)
+            || self.time_budget.is_some_and(|budget| elapsed >= budget)
+            || (n >= self.min_sample_size && stats.max_std_error() <= self.tolerance)
+    }
+}
+
+/// Estimator of semivalues from random samples. Every sample is an unbiased estimate of
+/// all sellers' values, and samples are independent, so their variance can be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Estimator {
+    /// Marginal contributions along a random permutation.
+    Permutation,
+    /// Average of the marginal contributions along a random permutation and its reverse.
+    Antithetic,
+    /// For each seller and each coalition size `k`, the marginal contribution to a random
+    /// coalition of `k` other sellers, weighted by the size weight of `k`.
+    Stratified,
+}
+
+impl FromStr for Estimator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "permutation" | "perm" => Self::Permutation,
+            "antithetic" => Self::Antithetic,
+            "stratified" => Self::Stratified,
+            _ => bail!(
+                "Unknown estimator {}. accepted values: [perm, antithetic, stratified]",
+                s
+            ),
         })
-        .reduce(
-            || Ok(HashMap::new()),
-            |a: Result<HashMap<SellerId, f64>>, b: Result<HashMap<SellerId, f64>>| {
-                let a = a?;
-                let b = b?;
-                Ok(merge_sv(a, b))
-            },
-        )?;
-    shapley_values.par_iter_mut().for_each(|(_, v)| {
-        *v /= sample_size as f64;
-    });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SamplingOpts {
+    pub value: Semivalue,
+    pub estimator: Estimator,
+    /// Truncate permutation walks once the prefix utility is within this of the grand
+    /// coalition's utility.
+    pub truncation: Option<f64>,
+    pub seed: u64,
+    /// Also estimate the Shapley interaction index of every seller pair from the same
+    /// permutations.
+    pub interactions: bool,
+}
+
+/// Marginal contributions of every seller in one sample, and the discrete derivatives of
+/// every seller pair if interactions are estimated.
+#[derive(Debug, Default)]
+struct Sample {
+    values: HashMap<SellerId, f64>,
+    interactions: HashMap<(SellerId, SellerId), f64>,
+}
+
+/// Truncation of permutation walks in the style of TMC-Shapley: once the utility of the
+/// prefix is within `tolerance` of the grand coalition, the remaining sellers are given a
+/// marginal contribution of 0 without evaluating the utility. Exact at tolerance 0 for
+/// monotone utilities, and biased for others, so only allowed for them.
+#[derive(Debug, Clone, Copy)]
+struct Truncation {
+    grand_utility: f64,
+    tolerance: f64,
+}
+
+struct Sampler<'a> {
+    dataset: &'a DataSet,
+    utility: &'a dyn Utility,
+    cache: DashMap<SellerSet, f64>,
+    size_weights: Vec<f64>,
+    structure: Option<SellerStructure<'a>>,
+    estimator: Estimator,
+    truncation: Option<Truncation>,
+    seed: u64,
+    interactions: bool,
+}
+
+impl<'a> Sampler<'a> {
+    fn new(dataset: &'a DataSet, utility: &'a dyn Utility, opts: &SamplingOpts) -> Result<Self> {
+        let mut sampler = Self {
+            dataset,
+            utility,
+            cache: DashMap::new(),
+            size_weights: opts.value.size_weights(dataset.sellers.len()),
+            structure: SellerStructure::of(dataset, &opts.value)?,
+            estimator: opts.estimator,
+            truncation: None,
+            seed: opts.seed,
+            interactions: opts.interactions,
+        };
+        ensure!(
+            !opts.interactions
+                || (opts.value == Semivalue::Shapley
+                    && sampler.structure.is_none()
+                    && opts.estimator == Estimator::Permutation),
+            "interactions are only estimated for the Shapley value with the permutation estimator"
+        );
+        // reversed weighted orders are not weighted orders, but reversed union-consistent
+        // orders are still union-consistent.
+        match (sampler.structure, opts.estimator) {
+            (Some(SellerStructure::Weights(_)), Estimator::Antithetic | Estimator::Stratified) => {
+                bail!("seller weights need the permutation estimator")
+            }
+            (Some(SellerStructure::Unions(_)), Estimator::Stratified) => {
+                bail!("seller unions need the permutation or antithetic estimator")
+            }
+            _ => {}
+        }
+        if let Some(tolerance) = opts.truncation {
+            ensure!(
+                opts.estimator != Estimator::Stratified,
+                "truncation only applies to permutation walks"
+            );
+            ensure!(
+                utility.is_monotone(),
+                "truncation is biased for utilities that are not monotone, e.g. sums"
+            );
+            let grand_utility = sampler.utility(dataset.sellers.clone())?;
+            info!("truncate at {} ± {}", grand_utility, tolerance);
+            sampler.truncation = Some(Truncation {
+                grand_utility,
+                tolerance,
+            });
+        }
+        Ok(sampler)
+    }
+
+    fn utility(&self, subset: SellerSet) -> Result<f64> {
+        subset_utility_with_cache(self.dataset, self.utility, subset, &self.cache)
+    }
+
+    /// Draw sample #`i`.
+    fn draw(&self, i: usize) -> Result<Sample> {
+        info!("sample #{}", i);
+        let mut rng = sample_rng(self.seed, i);
+        let mut sellers: Vec<SellerId> = self.dataset.sellers.iter().copied().collect();
+        let mut sample = Sample::default();
+        sample.values = match self.estimator {
+            Estimator::Permutation => {
+                self.shuffle(&mut sellers, &mut rng);
+                if self.interactions {
+                    sample.interactions = self.pair_walk(&sellers)?;
+                }
+                self.walk(&sellers)?
+            }
+            Estimator::Antithetic => {
+                self.shuffle(&mut sellers, &mut rng);
+                let forward = self.walk(&sellers)?;
+                sellers.reverse();
+                let mut ans = merge_sv(forward, self.walk(&sellers)?);
+                ans.values_mut().for_each(|v| *v /= 2.);
+                ans
+            }
+            Estimator::Stratified => self.stratified(&sellers, &mut rng)?,
+        };
+        info!("sample #{} done", i);
+        Ok(sample)
+    }
+
+    fn shuffle(&self, sellers: &mut Vec<SellerId>, rng: &mut ChaCha8Rng) {
+        match self.structure {
+            Some(structure) => structure.shuffle(sellers, rng),
+            None => sellers.shuffle(rng),
+        }
+    }
+
+    fn fill_result(
+        &self,
+        stats: &SampleStats,
+        interactions: HashMap<(SellerId, SellerId), f64>,
+        result: &mut ShapleyResult,
+    ) {
+        stats.fill_result(result);
+        let n = stats.count() as f64;
+        result.interactions = interactions
+            .into_iter()
+            .map(|((i, j), v)| (i, j, v / n))
+            .collect();
+        result.interactions.sort_by_key(|(i, j, _)| (*i, *j));
+        if let Some(SellerStructure::Unions(unions)) = self.structure {
+            result.union_values = unions.union_values(&result.shapley_values);
+        }
+    }
+
+    /// Marginal contribution of every seller along a permutation. The prefix before position
+    /// `k` is a uniformly random `k`-coalition, so weighing it by `n` times the size weight of
+    /// `k` keeps the estimate unbiased for any semivalue; for Shapley the factor is 1.
+    fn walk(&self, sellers: &[SellerId]) -> Result<HashMap<SellerId, f64>> {
+        let n = sellers.len() as f64;
+        let mut last_utility = 0.;
+        let mut seller_set = SellerSet::default();
+        let mut ans = HashMap::new();
+
+        for (k, &seller) in sellers.iter().enumerate() {
+            if let Some(t) = self.truncation {
+                if (t.grand_utility - last_utility).abs() <= t.tolerance {
+                    ans.insert(seller, 0.);
+                    continue;
+                }
+            }
+
+            seller_set.insert(seller);
+            let subset_utility = self.utility(seller_set.clone())?;
+            ans.insert(
+                seller,
+                n * self.size_weights[k] * (subset_utility - last_utility),
+            );
+            last_utility = subset_utility;
+        }
+        Ok(ans)
+    }
+
+    /// Discrete derivative of every pair `(i, j)`, `i < j`, at the sellers before `i` but `j`.
+    /// Restricted to the sellers but `j`, a permutation is uniformly random with `i` standing
+    /// for the pair, which is what the Shapley interaction index averages over.
+    fn pair_walk(&self, sellers: &[SellerId]) -> Result<HashMap<(SellerId, SellerId), f64>> {
+        let mut ans = HashMap::new();
+        for (pos, &i) in sellers.iter().enumerate() {
+            for &j in sellers.iter().filter(|j| **j > i) {
+                let mut subset: SellerSet =
+                    sellers[..pos].iter().copied().filter(|s| *s != j).collect();
+                let u = self.utility(subset.clone())?;
+                subset.insert(i);
+                let u_i = self.utility(subset.clone())?;
+                subset.insert(j);
+                let u_ij = self.utility(subset.clone())?;
+                subset.remove(&i);
+                let u_j = self.utility(subset)?;
+                let delta = u_ij - u_i - u_j + u;
+                if delta != 0. {
+                    ans.insert((i, j), delta);
+                }
+            }
+        }
+        Ok(ans)
+    }
+
+    /// One random coalition per seller and coalition size.
+    fn stratified(
+        &self,
+        sellers: &[SellerId],
+        rng: &mut ChaCha8Rng,
+    ) -> Result<HashMap<SellerId, f64>> {
+        let n = sellers.len();
+        let mut ans = HashMap::new();
+        for &seller in sellers {
+            let mut others: Vec<SellerId> =
+                sellers.iter().copied().filter(|s| *s != seller).collect();
+            let mut contribution = 0.;
+            for k in 0..n {
+                others.shuffle(rng);
+                let mut subset: SellerSet = others[..k].iter().copied().collect();
+                let utility_without_seller = self.utility(subset.clone())?;
+                subset.insert(seller);
+                let utility_with_seller = self.utility(subset)?;
+                contribution +=
+                    self.size_weights[k] * (utility_with_seller - utility_without_seller);
+            }
+            ans.insert(seller, contribution);
+        }
+        Ok(ans)
+    }
+}
+
+pub fn permutation_scheme(
+    dataset: &DataSet,
+    utility: &dyn Utility,
+    sample_size: usize,
+    opts: &SamplingOpts,
+) -> Result<ShapleyResult> {
+    info!(
+        "permutation scheme ({}, {:?})...",
+        opts.value, opts.estimator
+    );
+    let begin = Instant::now();
+    let sampler = Sampler::new(dataset, utility, opts)?;
+
+    let samples = (0..sample_size)
+        .into_par_iter()
+        .map(|i| sampler.draw(i))
+        .collect::<Result<Vec<Sample>>>()?;
+    // accumulate in sample order, so that the result does not depend on the number of threads.
+    let mut stats = SampleStats::new(&dataset.sellers);
+    let mut interactions = HashMap::new();
+    for sample in samples {
+        stats.push(&sample.values);
+        interactions = merge_sv(interactions, sample.interactions);
+    }
 
     let total_time = Instant::now() - begin;
     let avg_time = total_time / dataset.sellers.len() as u32;
     info!("done in {:?}", total_time);
-    Ok(ShapleyResult {
-        shapley_values,
+    let mut result = ShapleyResult {
         avg_time,
         total_time,
         ..Default::default()
-    })
+    };
+    sampler.fill_result(&stats, interactions, &mut result);
+    Ok(result)
+}
+
+/// Draw samples until `convergence` says to stop.
+///
+/// Samples are drawn in parallel batches but accumulated in order, checking the stopping rule
+/// after each one, so the number of samples used does not depend on the number of threads.
+pub fn permutation_scheme_adaptive(
+    dataset: &DataSet,
+    utility: &dyn Utility,
+    convergence: &Convergence,
+    opts: &SamplingOpts,
+) -> Result<ShapleyResult> {
+    info!(
+        "adaptive permutation scheme ({}, {:?})...",
+        opts.value, opts.estimator
+    );
+    let begin = Instant::now();
+    let sampler = Sampler::new(dataset, utility, opts)?;
+    let batch_size = rayon::current_num_threads();
+
+    let mut stats = SampleStats::new(&dataset.sellers);
+    let mut interactions = HashMap::new();
+    'sampling: loop {
+        let start = stats.count();
+        let samples = (start..start + batch_size)
+            .into_par_iter()
+            .map(|i| sampler.draw(i))
+            .collect::<Result<Vec<Sample>>>()?;
+        for sample in samples {
+            stats.push(&sample.values);
+            interactions = merge_sv(interactions, sample.interactions);
+            if convergence.is_done(&stats, begin.elapsed()) {
+                break 'sampling;
+            }
+        }
+        info!(
+            "{} samples, max standard error {}",
+            stats.count(),
+            stats.max_std_error()
+        );
+    }
+
+    let total_time = Instant::now() - begin;
+    let avg_time = total_time / dataset.sellers.len() as u32;
+    info!("done with {} samples in {:?}", stats.count(), total_time);
+    let mut result = ShapleyResult {
+        avg_time,
+        total_time,
+        ..Default::default()
+    };
+    sampler.fill_result(&stats, interactions, &mut result);
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::test_data_dir;
+    use crate::{
+        alg::proposed::{proposed_scheme, ProposedOpts},
+        utils::load_world,
+        TupleCount,
+    };
+
+    fn opts(truncation: Option<f64>) -> SamplingOpts {
+        SamplingOpts {
+            value: Semivalue::Shapley,
+            estimator: Estimator::Permutation,
+            truncation,
+            seed: 42,
+            interactions: false,
+        }
+    }
 
     #[test]
     fn test() {
         polars_core::POOL.install(|| {
-            let data_dir = test_data_dir();
-            let world = DataSet::load(
-                "world",
-                data_dir.join("world"),
-                data_dir.join("world-metadata"),
-            )
-            .unwrap();
-            let r = permutation_scheme(&world, 50).unwrap();
-            dbg!(&r);
+            let world = load_world();
+            let r = permutation_scheme(&world, &TupleCount, 50, &opts(None)).unwrap();
+            let actual = r.shapley_values.values().sum::<f64>();
+            assert!((actual - 30670.).abs() < 1e-5);
+
+            let single_thread = rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .unwrap();
+            let r2 = single_thread
+                .install(|| permutation_scheme(&world, &TupleCount, 50, &opts(None)))
+                .unwrap();
+            assert_eq!(r.shapley_values, r2.shapley_values);
+        });
+    }
+
+    #[test]
+    fn test_adaptive() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let mut convergence = Convergence {
+                tolerance: f64::INFINITY,
+                time_budget: None,
+                min_sample_size: 8,
+                max_sample_size: None,
+            };
+            let r = permutation_scheme_adaptive(&world, &TupleCount, &convergence, &opts(None))
+                .unwrap();
+            assert_eq!(r.num_samples, 8);
+
+            convergence.tolerance = 0.;
+            convergence.max_sample_size = Some(20);
+            let r = permutation_scheme_adaptive(&world, &TupleCount, &convergence, &opts(None))
+                .unwrap();
+            assert_eq!(r.num_samples, 20);
             let actual = r.shapley_values.values().sum::<f64>();
             assert!((actual - 30670.).abs() < 1e-5);
+            // the first 20 samples are the same ones as with a fixed sample size.
+            let fixed = permutation_scheme(&world, &TupleCount, 20, &opts(None)).unwrap();
+            assert_eq!(r.shapley_values, fixed.shapley_values);
+            for (seller, (lo, hi)) in &r.confidence_intervals {
+                assert!(*lo <= r.shapley_values[seller] && r.shapley_values[seller] <= *hi);
+            }
+        });
+    }
+
+    #[test]
+    fn test_truncation() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            // tuple count is monotone, so truncation at tolerance 0 is exact.
+            let r = permutation_scheme(&world, &TupleCount, 20, &opts(None)).unwrap();
+            let truncated = permutation_scheme(&world, &TupleCount, 20, &opts(Some(0.))).unwrap();
+            assert_eq!(r.shapley_values, truncated.shapley_values);
+
+            let truncated =
+                permutation_scheme(&world, &TupleCount, 20, &opts(Some(1000.))).unwrap();
+            let actual = truncated.shapley_values.values().sum::<f64>();
+            assert!((30670. - 1000. - 1e-5..=30670. + 1e-5).contains(&actual));
+
+            // a sum may drop as sellers join, so its walks cannot be truncated.
+            let sum = crate::SumOf {
+                column: "Population".to_string(),
+            };
+            assert!(permutation_scheme(&world, &sum, 20, &opts(Some(0.))).is_err());
+        });
+    }
+
+    #[test]
+    fn test_estimators() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            for estimator in [Estimator::Antithetic, Estimator::Stratified] {
+                let opts = SamplingOpts {
+                    estimator,
+                    ..opts(None)
+                };
+                let r = permutation_scheme(&world, &TupleCount, 20, &opts).unwrap();
+                assert_eq!(r.variances.len(), world.sellers.len());
+                let actual = r.shapley_values.values().sum::<f64>();
+                if estimator == Estimator::Antithetic {
+                    // every permutation walk adds up to the grand coalition's utility.
+                    assert!((actual - 30670.).abs() < 1e-5);
+                } else {
+                    assert!((actual - 30670.).abs() / 30670. < 0.1);
+                }
+            }
+
+            let opts = SamplingOpts {
+                estimator: Estimator::Stratified,
+                ..opts(Some(0.))
+            };
+            assert!(permutation_scheme(&world, &TupleCount, 20, &opts).is_err());
+        });
+    }
+
+    #[test]
+    fn test_interactions() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let opts = SamplingOpts {
+                interactions: true,
+                ..opts(None)
+            };
+            let r = permutation_scheme(&world, &TupleCount, 50, &opts).unwrap();
+            let expected = proposed_scheme(
+                &world,
+                &TupleCount,
+                &ProposedOpts {
+                    interactions: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let actual: HashMap<_, _> = r
+                .interactions
+                .iter()
+                .map(|(i, j, v)| ((*i, *j), *v))
+                .collect();
+            // within the sampling error of 50 permutations, relative to each index and to the
+            // largest one.
+            let scale = expected
+                .interactions
+                .iter()
+                .map(|(_, _, v)| v.abs())
+                .fold(0., f64::max);
+            assert!(scale > 0.);
+            for (i, j, v) in &expected.interactions {
+                let v_actual = actual.get(&(*i, *j)).copied().unwrap_or_default();
+                assert!((v_actual - v).abs() <= 0.2 * v.abs() + 0.05 * scale);
+            }
         });
     }
 }
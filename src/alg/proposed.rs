@@ -1,9 +1,18 @@
 use crate::{
-    alg::join::join, utils::merge_sv, DataSet, RowId, ShapleyResult, PLANS, ROW_ID_COL_NAME,
+    alg::join::join, utils::merge_sv, DataSet, JoinKind, JoinPlan, RowId, RowOwners, SellerId,
+    SellerSet, SellerStructure, Semivalue, ShapleyResult, TableContribution, Utility,
+    ROW_ID_COL_NAME,
 };
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
+use polars::prelude::{DataFrame, GroupsProxy};
+use polars_core::export::arrow::compute::aggregate::estimated_bytes_size;
 use rayon::prelude::*;
-use std::{collections::HashMap, mem::drop, time::Instant};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::drop,
+    path::PathBuf,
+    time::Instant,
+};
 
 mod synthesis;
 use synthesis::Synthesis;
@@ -11,113 +20,1014 @@ use synthesis::Synthesis;
 mod synthesis_sv;
 use synthesis_sv::*;
 
-pub fn proposed_scheme(dataset: &DataSet, scale: f64) -> Result<ShapleyResult> {
-    info!("proposed scheme...");
+mod attribution;
+use attribution::AttributionWriter;
+
+/// Units whose values are computed, and written out, at a time.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Syntheses whose values are kept for units of later chunks.
+const MEMO_CAPACITY: usize = 1 << 20;
+
+/// Estimated bytes a joined tuple takes on top of its columns, for its synthesis.
+const TUPLE_OVERHEAD: usize = 256;
+
+/// Joined tuples per row of the chunked table assumed before any chunk is joined, so that the
+/// first chunk is a small probe of the fanout of the join.
+const PROBE_FANOUT: f64 = 64.;
+
+#[derive(Debug, Clone)]
+pub struct ProposedOpts {
+    pub value: Semivalue,
+    /// Above this ratio of sellers to syntheses the lookup kernel is used over the comb kernel.
+    pub scale: f64,
+    /// Also compute the Shapley interaction index of every seller pair.
+    pub interactions: bool,
+    /// Stream the value of every seller in every unit to this csv file or parquet directory.
+    pub attribution: Option<PathBuf>,
+    /// Break the value of every seller down by the tables and rows it owns.
+    pub contributions: bool,
+    /// Join the largest inner table a chunk of rows at a time, sized so that the joined tuples
+    /// of a chunk, with their syntheses and row ids, take about this many bytes. The first
+    /// chunk is a small probe, and later ones are sized by the largest fanout seen so far.
+    /// The tables themselves and the hash tables of the join are not counted.
+    pub memory_limit: Option<usize>,
+}
+
+impl Default for ProposedOpts {
+    fn default() -> Self {
+        Self {
+            value: Semivalue::Shapley,
+            scale: 1.,
+            interactions: false,
+            attribution: None,
+            contributions: false,
+            memory_limit: None,
+        }
+    }
+}
+
+pub fn proposed_scheme(
+    dataset: &DataSet,
+    utility: &dyn Utility,
+    opts: &ProposedOpts,
+) -> Result<ShapleyResult> {
+    let value = &opts.value;
+    info!("proposed scheme ({})...", value);
     let begin = Instant::now();
+    let structure = SellerStructure::of(dataset, value)?;
+    ensure!(
+        !opts.interactions || (*value == Semivalue::Shapley && structure.is_none()),
+        "interactions are only computed for the Shapley value"
+    );
 
-    info!("join...");
-    let join_df = join(
-        |table_name| dataset.tables.get(table_name).map(|t| &t.df),
-        PLANS
-            .get(dataset.name.as_str())
-            .context("cannot find join plan")?,
-    )?;
-
-    info!("extract row_id_columns...");
-    let row_id_columns: Vec<(String, Vec<RowId>)> = join_df
-        .columns(
-            dataset
-                .tables
-                .keys()
-                .map(|t| format!("{}:{}", ROW_ID_COL_NAME, t)),
-        )?
-        .into_iter()
-        .map(|column| {
-            let table_name = column.name().rsplit(':').next().unwrap().to_string();
-            let row_ids = column
-                .u64()
-                .unwrap()
-                .into_iter()
-                .map(|row_id| RowId::new(row_id.unwrap()))
+    let plan = dataset.plan();
+    let tables: Vec<&str> = plan.tables().collect();
+    // row ids of the tuples are only needed after extracting syntheses for the attribution
+    // and contributions.
+    let keep_row_ids = opts.attribution.is_some() || opts.contributions;
+    let mut writer = match &opts.attribution {
+        Some(path) => {
+            info!("write attribution to {}...", path.display());
+            Some(AttributionWriter::new(
+                path,
+                tables.iter().map(|t| t.to_string()).collect(),
+            )?)
+        }
+        None => None,
+    };
+
+    // tuples of a unit grouped by values may come from any row of a table, and full outer
+    // joins add tuples that come from none of them. Otherwise every unit has a single row of
+    // each inner table, so joining a chunk of the rows of one at a time partitions the units.
+    let splittable = utility.unit_columns().is_none()
+        && plan.projection.is_none()
+        && !plan
+            .steps
+            .iter()
+            .any(|s| matches!(s.join_type, JoinKind::Outer));
+    if opts.memory_limit.is_some() && !splittable {
+        warn!("cannot split the join of {} into chunks", dataset.name);
+    }
+    let height = |table_name: &str| dataset.tables.get(table_name).map_or(0, |t| t.df.height());
+    // the largest table bounds the joined tuples of a chunk best.
+    let chunk_table = match opts.memory_limit {
+        Some(_) if splittable => plan
+            .inner_tables()
+            .into_iter()
+            .max_by_key(|t| (height(t), *t == plan.init_table))
+            .unwrap_or(&plan.init_table),
+        _ => &plan.init_table,
+    };
+    let chunk_df = &dataset
+        .tables
+        .get(chunk_table)
+        .with_context(|| format!("cannot find table {}", chunk_table))?
+        .df;
+    let chunk_rows = chunk_df.height();
+    let tuple_size = tables
+        .iter()
+        .map(|t| row_size(&dataset.tables[*t].df))
+        .sum::<usize>()
+        + TUPLE_OVERHEAD
+        + if keep_row_ids {
+            tables.len() * std::mem::size_of::<Option<RowId>>()
+        } else {
+            0
+        };
+    // most joined tuples per row of the chunked table seen so far.
+    let mut fanout: Option<f64> = None;
+
+    let solve = |syn: &Synthesis| match (syn.is_linear(), structure) {
+        (Some(_), Some(SellerStructure::Weights(weights))) => {
+            (cal_weighted_sv_linear(syn, weights), (1, 0, 0))
+        }
+        (Some((count, k)), None) if *value == Semivalue::Shapley => {
+            (cal_sv_linear(syn, count, k), (1, 0, 0))
+        }
+        _ => {
+            let (ans, lookup_count, comb_count) =
+                cal_sv_non_linear(syn, value, structure, opts.scale);
+            (ans, (0, lookup_count, comb_count))
+        }
+    };
+    // semivalues do not depend on the names of sellers, so syntheses equal up to renaming
+    // sellers share their values. Weighted Shapley and Owen values do depend on them.
+    let symmetric = structure.is_none();
+    // values of the sellers, in the order of their labels, of the canonical form of the
+    // syntheses solved so far, up to MEMO_CAPACITY of them.
+    let mut memo: HashMap<Vec<SellerSet>, Vec<f64>> = HashMap::new();
+    let mut distinct_syntheses = 0;
+    let mut num_units = 0;
+    let mut joined_sellers = HashSet::new();
+    let mut shapley_values = HashMap::new();
+    let mut row_values = HashMap::new();
+    let mut interactions = HashMap::new();
+    // interactions of the canonical form of syntheses, `None` if over too many sellers.
+    let mut interaction_memo: HashMap<Vec<SellerSet>, Option<Interactions>> = HashMap::new();
+    let mut skipped_interactions = 0;
+    let (mut linear_count, mut lookup_count, mut comb_count) = (0, 0, 0);
+
+    let mut offset = 0;
+    loop {
+        let len = match opts.memory_limit {
+            Some(limit) if splittable => {
+                let fanout = fanout.unwrap_or(PROBE_FANOUT);
+                ((limit as f64 / (tuple_size as f64 * fanout)) as usize).max(1)
+            }
+            _ => chunk_rows,
+        };
+        let chunk = chunk_df.slice(offset as i64, len);
+        info!(
+            "join rows {}..{} of {}...",
+            offset,
+            offset + chunk.height(),
+            chunk_table
+        );
+        let join_df = join(
+            |table_name| {
+                if table_name == chunk_table {
+                    Some(&chunk)
+                } else {
+                    dataset.tables.get(table_name).map(|t| &t.df)
+                }
+            },
+            plan,
+        )?;
+        if chunk.height() > 0 {
+            let chunk_fanout = join_df.height() as f64 / chunk.height() as f64;
+            fanout = Some(fanout.unwrap_or(1.).max(chunk_fanout));
+        }
+        let batch = Batch::new(dataset, plan, utility, join_df, keep_row_ids)?;
+
+        info!(
+            "compute shapley value of {} units...",
+            batch.syntheses.len()
+        );
+        for (c, syntheses) in batch.syntheses.chunks(CHUNK_SIZE).enumerate() {
+            let canonical: Vec<_> = syntheses
+                .par_iter()
+                .enumerate()
+                .filter(|(_, (_, _, weight))| *weight != 0.)
+                .map(|(i, (_, syn, weight))| {
+                    (c * CHUNK_SIZE + i, syn.canonical(symmetric), *weight)
+                })
                 .collect();
-            (table_name, row_ids)
-        })
-        .collect();
-    let rows = join_df.shape().0;
-    let cols = row_id_columns.len();
-    drop(join_df);
-
-    info!("extract syntheses...");
-    let row_id_columns_ref = &row_id_columns;
-    let syntheses: Vec<_> = (0..rows)
-        .into_par_iter()
-        .map(move |i| {
-            let seller_sets = (0..cols).map(move |j| {
-                let (table_name, row_ids) = &row_id_columns_ref[j];
-                let row_id = row_ids[i];
-                &dataset.tables[table_name].seller_map[&row_id]
-            });
-            let mut syn = Synthesis::from_seller_sets(seller_sets);
-            syn.minimal();
-            syn
-        })
-        .collect();
-    drop(row_id_columns);
-
-    info!("compute shapley value...");
-    let (shapley_values, linear_count, lookup_count, comb_count) = syntheses
-        .par_iter()
-        .enumerate()
-        .map(|(i, syn)| {
-            if i % 100_000 == 0 {
-                info!("syn #{}", i);
-            }
-            if let Some((count, k)) = syn.is_linear() {
-                let ans = cal_sv_linear(syn, count, k);
-                (ans, 1usize, 0, 0)
-            } else {
-                let (ans, lookup_count, comb_count) = cal_sv_non_linear(syn, scale);
-                (ans, 0usize, lookup_count, comb_count)
+
+            // the memo holds at most a chunk of syntheses past its capacity.
+            if memo.len() > MEMO_CAPACITY {
+                info!("clear memo of {} syntheses", memo.len());
+                memo.clear();
             }
-        })
-        .reduce(
-            || (HashMap::new(), 0, 0, 0),
-            |a, b| (merge_sv(a.0, b.0), a.1 + b.1, a.2 + b.2, a.3 + b.3),
+            let unsolved: HashSet<&Vec<SellerSet>> = canonical
+                .iter()
+                .map(|(_, (sets, _), _)| sets)
+                .filter(|sets| !memo.contains_key(*sets))
+                .collect();
+            let unsolved: Vec<_> = unsolved.into_iter().collect();
+            let solved: Vec<_> = unsolved
+                .into_par_iter()
+                .map(|sets| {
+                    let syn = Synthesis::new(sets.iter().cloned().collect());
+                    let (ans, counts) = solve(&syn);
+                    let mut labels: Vec<_> = syn.unique_sellers().into_iter().collect();
+                    labels.sort();
+                    let values = labels
+                        .iter()
+                        .map(|label| ans.get(label).copied().unwrap_or_default())
+                        .collect();
+                    (sets.clone(), values, counts)
+                })
+                .collect();
+            distinct_syntheses += solved.len();
+            for (sets, values, (linear, lookup, comb)) in solved {
+                memo.insert(sets, values);
+                linear_count += linear;
+                lookup_count += lookup;
+                comb_count += comb;
+            }
+
+            let memo_ref = &memo;
+            let chunk_values: Vec<(usize, HashMap<SellerId, f64>)> = canonical
+                .into_par_iter()
+                .map(|(unit, (sets, sellers), weight)| {
+                    let sv = sellers
+                        .into_iter()
+                        .zip(&memo_ref[&sets])
+                        .map(|(seller, v)| (seller, v * weight))
+                        .collect();
+                    (unit, sv)
+                })
+                .collect();
+
+            if let (Some(writer), Some(columns)) = (&mut writer, &batch.row_id_columns) {
+                writer.write(
+                    &dataset.registry,
+                    chunk_values.iter().map(|(unit, sv)| {
+                        let first = batch.syntheses[*unit].0;
+                        let row_ids = columns.iter().map(|(_, row_ids)| row_ids[first]).collect();
+                        (num_units + unit, row_ids, sv)
+                    }),
+                )?;
+            }
+            if let (true, Some(columns)) = (opts.contributions, &batch.row_id_columns) {
+                let chunk_row_values = chunk_values
+                    .par_iter()
+                    .map(|(unit, sv)| {
+                        let first = batch.syntheses[*unit].0;
+                        match &batch.units {
+                            Some(units) => row_contributions(
+                                dataset,
+                                columns,
+                                units[*unit].1.iter().map(|&i| i as usize),
+                                sv,
+                            ),
+                            None => row_contributions(dataset, columns, std::iter::once(first), sv),
+                        }
+                    })
+                    .try_reduce(HashMap::new, |a, b| Ok(merge_sv(a, b)))?;
+                row_values = merge_sv(row_values, chunk_row_values);
+            }
+            for (_, sv) in chunk_values {
+                shapley_values = merge_sv(shapley_values, sv);
+            }
+        }
+
+        if opts.interactions {
+            info!("compute interactions...");
+            // interactions are only computed without a seller structure, so symmetric.
+            let canonical: Vec<_> = batch
+                .syntheses
+                .par_iter()
+                .filter(|(_, _, weight)| *weight != 0.)
+                .map(|(_, syn, weight)| (syn.canonical(true), *weight))
+                .collect();
+            if interaction_memo.len() > MEMO_CAPACITY {
+                info!(
+                    "clear interaction memo of {} syntheses",
+                    interaction_memo.len()
+                );
+                interaction_memo.clear();
+            }
+            let unsolved: HashSet<&Vec<SellerSet>> = canonical
+                .iter()
+                .map(|((sets, _), _)| sets)
+                .filter(|sets| !interaction_memo.contains_key(*sets))
+                .collect();
+            let solved: Vec<_> = unsolved
+                .into_par_iter()
+                .map(|sets| {
+                    let syn = Synthesis::new(sets.iter().cloned().collect());
+                    (sets.clone(), cal_interactions(&syn))
+                })
+                .collect();
+            interaction_memo.extend(solved);
+
+            let memo_ref = &interaction_memo;
+            let (batch_interactions, skipped) = canonical
+                .into_par_iter()
+                .map(|((sets, sellers), weight)| match &memo_ref[&sets] {
+                    Some(ans) => {
+                        let ans = ans
+                            .iter()
+                            .map(|((i, j), v)| {
+                                let (i, j) = (sellers[i.0 as usize], sellers[j.0 as usize]);
+                                ((i.min(j), i.max(j)), *v)
+                            })
+                            .collect();
+                        (scale_sv(ans, weight), 0)
+                    }
+                    None => (HashMap::new(), 1),
+                })
+                .reduce(
+                    || (HashMap::new(), 0),
+                    |a, b| (merge_sv(a.0, b.0), a.1 + b.1),
+                );
+            interactions = merge_sv(interactions, batch_interactions);
+            skipped_interactions += skipped;
+        }
+
+        num_units += batch.syntheses.len();
+        joined_sellers.extend(batch.sellers);
+        offset += chunk.height();
+        if offset >= chunk_rows {
+            break;
+        }
+    }
+    if let Some(writer) = writer {
+        writer.finish()?;
+    }
+
+    let memo_hit_rate = if num_units == 0 {
+        0.
+    } else {
+        1. - distinct_syntheses as f64 / num_units as f64
+    };
+    info!(
+        "{} distinct syntheses among {} units ({:.1}% memo hits)",
+        distinct_syntheses,
+        num_units,
+        memo_hit_rate * 100.
+    );
+    drop(memo);
+    drop(interaction_memo);
+    if skipped_interactions > 0 {
+        warn!(
+            "skipped interactions of {} units over more than {} sellers",
+            skipped_interactions, MAX_INTERACTION_SELLERS
+        );
+    }
+
+    let mut unjoined: Vec<SellerId> = dataset
+        .tables
+        .values()
+        .flat_map(|t| t.seller_map.values())
+        .flat_map(|sellers| sellers.iter().copied())
+        .filter(|seller| !joined_sellers.contains(seller))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    unjoined.sort();
+    if !unjoined.is_empty() {
+        warn!(
+            "{} sellers own rows but none of them survives the join: {}",
+            unjoined.len(),
+            unjoined
+                .iter()
+                .map(|s| dataset.registry.name(*s))
+                .collect::<Vec<_>>()
+                .join(", ")
         );
+    }
+    // sellers in no synthesis add nothing to any coalition.
+    for seller in dataset.sellers.iter() {
+        shapley_values.entry(*seller).or_insert(0.);
+    }
+
+    let mut contributions: HashMap<SellerId, HashMap<String, TableContribution>> = HashMap::new();
+    for ((seller, table, row_id), v) in row_values {
+        let contribution = contributions
+            .entry(seller)
+            .or_default()
+            .entry(tables[table].to_string())
+            .or_default();
+        contribution.value += v;
+        contribution.rows.insert(row_id, v);
+    }
+
+    let mut interactions: Vec<_> = interactions
+        .into_iter()
+        .map(|((i, j), v)| (i, j, v))
+        .collect();
+    interactions.sort_by_key(|(i, j, _)| (*i, *j));
 
     let total_time = Instant::now() - begin;
     let avg_time = total_time / dataset.sellers.len() as u32;
     info!("done in {:?}", total_time);
+    let union_values = match structure {
+        Some(SellerStructure::Unions(unions)) => unions.union_values(&shapley_values),
+        _ => HashMap::new(),
+    };
     Ok(ShapleyResult {
         shapley_values,
+        union_values,
+        interactions,
+        contributions,
         avg_time,
         total_time,
         linear_count,
         lookup_count,
         comb_count,
+        distinct_syntheses,
+        memo_hit_rate,
+        ..Default::default()
     })
 }
 
+/// Joined tuples of a chunk of the init table, grouped into units.
+struct Batch {
+    /// (first tuple, synthesis, weight) of each unit.
+    syntheses: Vec<(usize, Synthesis, f64)>,
+    /// (first tuple, tuples) of each unit, if tuples are grouped.
+    units: Option<Vec<(u32, Vec<u32>)>>,
+    /// Row ids of every tuple, by table, if kept.
+    row_id_columns: Option<Vec<(String, Vec<Option<RowId>>)>>,
+    /// Owners of the rows in the joined tuples.
+    sellers: HashSet<SellerId>,
+}
+
+impl Batch {
+    fn new(
+        dataset: &DataSet,
+        plan: &JoinPlan,
+        utility: &dyn Utility,
+        join_df: DataFrame,
+        keep_row_ids: bool,
+    ) -> Result<Self> {
+        info!("extract row_id_columns...");
+        // row ids are null for tables on the null-extended side of an outer join.
+        let row_id_columns: Vec<(String, Vec<Option<RowId>>)> = join_df
+            .columns(plan.tables().map(|t| format!("{}:{}", ROW_ID_COL_NAME, t)))?
+            .into_iter()
+            .map(|column| {
+                let table_name = column.name().rsplit(':').next().unwrap().to_string();
+                let row_ids = column
+                    .u64()
+                    .unwrap()
+                    .into_iter()
+                    .map(|row_id| row_id.map(RowId::new))
+                    .collect();
+                (table_name, row_ids)
+            })
+            .collect();
+        let rows = join_df.shape().0;
+        let cols = row_id_columns.len();
+
+        info!("extract utility units...");
+        // answer tuples of the query only keep the projected columns; weigh each joined tuple
+        // by the answer tuple it collapses into.
+        let weights = match &plan.projection {
+            Some(projection) => utility.weights(
+                &join_df.select(projection.iter().map(|c| c.as_str()).collect::<Vec<_>>())?,
+            )?,
+            None => utility.weights(&join_df)?,
+        };
+        // joined tuples that collapse into the same answer tuple form a unit, and so do
+        // tuples differing only in their semi join witnesses.
+        let semi_tables: HashSet<&str> = plan.semi_tables().collect();
+        let unit_columns: Option<Vec<String>> = match (utility.unit_columns(), &plan.projection) {
+            (Some(columns), _) => Some(columns.to_vec()),
+            (None, Some(projection)) => Some(projection.clone()),
+            (None, None) if !semi_tables.is_empty() => Some(
+                plan.tables()
+                    .filter(|t| !semi_tables.contains(t))
+                    .map(|t| format!("{}:{}", ROW_ID_COL_NAME, t))
+                    .collect(),
+            ),
+            (None, None) => None,
+        };
+        let units = match unit_columns {
+            Some(columns) => Some(
+                match join_df
+                    .groupby(columns.iter().map(|c| c.as_str()).collect::<Vec<_>>())?
+                    .get_groups()
+                {
+                    GroupsProxy::Idx(groups) => groups.clone(),
+                    GroupsProxy::Slice(groups) => groups
+                        .iter()
+                        .map(|&[first, len]| (first, (first..first + len).collect()))
+                        .collect(),
+                },
+            ),
+            None => None,
+        };
+        drop(join_df);
+
+        info!("extract syntheses...");
+        let row_id_columns_ref = &row_id_columns;
+        let syntheses: Vec<_> = (0..rows)
+            .into_par_iter()
+            .map(move |i| {
+                let seller_sets = (0..cols)
+                    .filter_map(|j| {
+                        let (table_name, row_ids) = &row_id_columns_ref[j];
+                        row_ids[i].map(|row_id| row_owners(dataset, table_name, row_id))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let mut syn = Synthesis::from_seller_sets(seller_sets.into_iter());
+                syn.minimal();
+                Ok(syn)
+            })
+            .collect::<Result<_>>()?;
+        let sellers = row_id_columns
+            .par_iter()
+            .flat_map(|(table_name, row_ids)| {
+                row_ids
+                    .par_iter()
+                    .filter_map(|row_id| *row_id)
+                    .map(move |row_id| row_owners(dataset, table_name, row_id))
+            })
+            .try_fold(HashSet::new, |mut sellers, owners| {
+                sellers.extend(owners?.sellers.iter().copied());
+                Ok::<_, anyhow::Error>(sellers)
+            })
+            .try_reduce(HashSet::new, |mut a, b| {
+                a.extend(b);
+                Ok(a)
+            })?;
+        let row_id_columns = keep_row_ids.then_some(row_id_columns);
+
+        let syntheses: Vec<(usize, Synthesis, f64)> = match &units {
+            None => syntheses
+                .into_iter()
+                .zip(weights)
+                .enumerate()
+                .map(|(i, (syn, weight))| (i, syn, weight))
+                .collect(),
+            Some(units) => {
+                info!("merge syntheses of {} units...", units.len());
+                let syntheses_ref = &syntheses;
+                let weights_ref = &weights;
+                units
+                    .par_iter()
+                    .map(|(first, unit)| {
+                        let mut syn = Synthesis::default();
+                        for &i in unit {
+                            syn.union(syntheses_ref[i as usize].clone());
+                        }
+                        syn.minimal();
+                        (*first as usize, syn, weights_ref[*first as usize])
+                    })
+                    .collect()
+            }
+        };
+        Ok(Self {
+            syntheses,
+            units,
+            row_id_columns,
+            sellers,
+        })
+    }
+}
+
+/// Estimated bytes of a row of `df`.
+fn row_size(df: &DataFrame) -> usize {
+    let bytes: usize = df
+        .get_columns()
+        .iter()
+        .flat_map(|column| column.chunks())
+        .map(|array| estimated_bytes_size(array.as_ref()))
+        .sum();
+    bytes / df.height().max(1)
+}
+
+/// Split the value of every seller in a unit equally among the distinct rows it owns in the
+/// `tuples` of the unit, keyed by `(seller, index of the table's row id column, row id)`.
+fn row_contributions(
+    dataset: &DataSet,
+    row_id_columns: &[(String, Vec<Option<RowId>>)],
+    tuples: impl Iterator<Item = usize>,
+    sv: &HashMap<SellerId, f64>,
+) -> Result<HashMap<(SellerId, usize, RowId), f64>> {
+    let mut rows: HashMap<SellerId, HashSet<(usize, RowId)>> = HashMap::new();
+    for i in tuples {
+        for (j, (table_name, row_ids)) in row_id_columns.iter().enumerate() {
+            if let Some(row_id) = row_ids[i] {
+                for seller in row_owners(dataset, table_name, row_id)?.sellers.iter() {
+                    if sv.contains_key(seller) {
+                        rows.entry(*seller).or_default().insert((j, row_id));
+                    }
+                }
+            }
+        }
+    }
+    Ok(rows
+        .into_iter()
+        .flat_map(|(seller, rows)| {
+            let v = sv[&seller] / rows.len() as f64;
+            rows.into_iter()
+                .map(move |(j, row_id)| ((seller, j, row_id), v))
+        })
+        .collect())
+}
+
+fn row_owners<'a>(dataset: &'a DataSet, table_name: &str, row_id: RowId) -> Result<RowOwners<'a>> {
+    dataset.tables[table_name]
+        .owners(row_id)
+        .with_context(|| format!("row id {} of {} has no owner", row_id, table_name))
+}
+
+/// Semivalues and interaction indices are linear, so a unit worth `weight` splits `weight`
+/// times the value of a unit worth 1.
+#[inline]
+fn scale_sv<K>(mut sv: HashMap<K, f64>, weight: f64) -> HashMap<K, f64> {
+    if weight != 1. {
+        sv.values_mut().for_each(|v| *v *= weight);
+    }
+    sv
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::{assert_world_sv, test_data_dir};
+    use crate::{
+        alg::{subset_utility::subset_utility, traditional::traditional_scheme},
+        get_plan, parse_utility,
+        utils::{assert_world_sv, load_world, TempDir},
+        ExprWeight, JoinStep, SellerInfo, SellerUnions, SellerWeights, TupleCount,
+    };
+    use polars::prelude::*;
 
     #[test]
     fn test() {
         polars_core::POOL.install(|| {
-            let data_dir = test_data_dir();
-            let world = DataSet::load(
-                "world",
-                data_dir.join("world"),
-                data_dir.join("world-metadata"),
-            )
-            .unwrap();
-            let r = proposed_scheme(&world, 1.).unwrap();
-            dbg!(&r);
+            let world = load_world();
+            let r = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap();
+            let actual = r.shapley_values.values().sum::<f64>();
+            assert!((actual - 30670.).abs() < 1e-5);
+            assert_world_sv(&r.shapley_values);
+            // world has few sellers, so most tuples share their synthesis with another.
+            assert!(r.distinct_syntheses > 0);
+            assert!(r.memo_hit_rate > 0.5);
+        });
+    }
+
+    #[test]
+    fn test_query() {
+        polars_core::POOL.install(|| {
+            let plan = get_plan("world")
+                .unwrap()
+                .with_filter("Percentage > 10".parse().unwrap())
+                .with_projection(&["Language"]);
+            let world = load_world().with_plan(plan);
+            let r = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap();
+            let grand = subset_utility(&world, &TupleCount, &world.sellers).unwrap();
             let actual = r.shapley_values.values().sum::<f64>();
+            assert!((actual - grand).abs() < 1e-5);
+        });
+    }
+
+    #[test]
+    fn test_weighted_utility() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let count = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap();
+            let double = ExprWeight {
+                expr: "2".parse().unwrap(),
+            };
+            let r = proposed_scheme(&world, &double, &ProposedOpts::default()).unwrap();
+            for (seller, v) in &count.shapley_values {
+                assert!((r.shapley_values[seller] - 2. * v).abs() < 1e-5);
+            }
+
+            // efficiency: values add up to the utility of the grand coalition.
+            for utility in ["sum:Percentage", "distinct:Language"] {
+                let utility = parse_utility(utility).unwrap();
+                let r =
+                    proposed_scheme(&world, utility.as_ref(), &ProposedOpts::default()).unwrap();
+                let grand = subset_utility(&world, utility.as_ref(), &world.sellers).unwrap();
+                let actual = r.shapley_values.values().sum::<f64>();
+                assert!((actual - grand).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_semivalue() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            for value in ["banzhaf", "beta:16,1"] {
+                let value: Semivalue = value.parse().unwrap();
+                // both kernels of the non linear path.
+                for scale in [1., 0.] {
+                    let r = proposed_scheme(
+                        &world,
+                        &TupleCount,
+                        &ProposedOpts {
+                            value,
+                            scale,
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+                    let expected = traditional_scheme(&world, &TupleCount, &value).unwrap();
+                    for (seller, v) in &expected.shapley_values {
+                        assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_seller_weights() {
+        polars_core::POOL.install(|| {
+            let mut world = load_world();
+            world.seller_weights = Some(SellerWeights(
+                [(SellerId(0), 3.), (SellerId(1), 0.5), (SellerId(4), 2.)]
+                    .into_iter()
+                    .collect(),
+            ));
+            let expected = traditional_scheme(&world, &TupleCount, &Semivalue::Shapley).unwrap();
+            let actual = expected.shapley_values.values().sum::<f64>();
             assert!((actual - 30670.).abs() < 1e-5);
+            for scale in [1., 0.] {
+                let r = proposed_scheme(
+                    &world,
+                    &TupleCount,
+                    &ProposedOpts {
+                        scale,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                for (seller, v) in &expected.shapley_values {
+                    assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+                }
+            }
+            assert!(proposed_scheme(
+                &world,
+                &TupleCount,
+                &ProposedOpts {
+                    value: Semivalue::Banzhaf,
+                    ..Default::default()
+                }
+            )
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn test_seller_unions() {
+        polars_core::POOL.install(|| {
+            let mut world = load_world();
+            world.seller_unions = Some(
+                SellerUnions::new([
+                    ("a".to_string(), vec![SellerId(0), SellerId(1)]),
+                    ("b".to_string(), vec![SellerId(2), SellerId(3), SellerId(4)]),
+                ])
+                .unwrap(),
+            );
+            let expected = traditional_scheme(&world, &TupleCount, &Semivalue::Shapley).unwrap();
+            assert_eq!(expected.union_values.len(), 3);
+            let actual = expected.union_values.values().sum::<f64>();
+            assert!((actual - 30670.).abs() < 1e-5);
+            for scale in [1., 0.] {
+                let r = proposed_scheme(
+                    &world,
+                    &TupleCount,
+                    &ProposedOpts {
+                        scale,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+                for (seller, v) in &expected.shapley_values {
+                    assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+                }
+                for (union, v) in &expected.union_values {
+                    assert!((r.union_values[union] - v).abs() < 1e-5);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_interactions() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let r = proposed_scheme(
+                &world,
+                &TupleCount,
+                &ProposedOpts {
+                    interactions: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(!r.interactions.is_empty());
+
+            // from the definition, over all coalitions.
+            let sellers: Vec<SellerId> = world.sellers.iter().copied().collect();
+            let n = sellers.len();
+            let utility: Vec<f64> = (0..1usize << n)
+                .map(|mask| {
+                    let subset = (0..n)
+                        .filter(|j| mask >> j & 1 == 1)
+                        .map(|j| sellers[j])
+                        .collect();
+                    subset_utility(&world, &TupleCount, &subset).unwrap()
+                })
+                .collect();
+            let mut expected = HashMap::new();
+            for i in 0..n {
+                for j in i + 1..n {
+                    let pair = 1 << i | 1 << j;
+                    let v: f64 = (0..1usize << n)
+                        .filter(|s| s & pair == 0)
+                        .map(|s| {
+                            crate::alg::semivalue::shapley_weight(s.count_ones() as usize, n - 1)
+                                * (utility[s | pair] - utility[s | 1 << i] - utility[s | 1 << j]
+                                    + utility[s])
+                        })
+                        .sum();
+                    expected.insert((sellers[i], sellers[j]), v);
+                }
+            }
+            let actual: HashMap<_, _> = r
+                .interactions
+                .iter()
+                .map(|(i, j, v)| ((*i, *j), *v))
+                .collect();
+            assert_eq!(actual.len(), r.interactions.len());
+            for (pair, v) in &expected {
+                assert!((actual.get(pair).copied().unwrap_or_default() - v).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_attribution() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let tmp_dir = TempDir::new("attribution");
+            let path = tmp_dir.path().join("attribution.csv");
+            let opts = ProposedOpts {
+                attribution: Some(path.clone()),
+                ..Default::default()
+            };
+            let r = proposed_scheme(&world, &TupleCount, &opts).unwrap();
+
+            let df = CsvReader::new(std::fs::File::open(&path).unwrap())
+                .finish()
+                .unwrap();
+            for table in get_plan("world").unwrap().tables() {
+                df.column(&format!("{}:{}", ROW_ID_COL_NAME, table))
+                    .unwrap();
+            }
+            let sellers = df.column("seller").unwrap().cast(&DataType::Utf8).unwrap();
+            let values = df
+                .column("value")
+                .unwrap()
+                .cast(&DataType::Float64)
+                .unwrap();
+            let mut actual: HashMap<SellerId, f64> = HashMap::new();
+            for (seller, v) in sellers
+                .utf8()
+                .unwrap()
+                .into_iter()
+                .zip(values.f64().unwrap())
+            {
+                let seller = world.registry.id(seller.unwrap()).unwrap();
+                *actual.entry(seller).or_default() += v.unwrap();
+            }
+            for (seller, v) in &r.shapley_values {
+                assert!((actual[seller] - v).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_contributions() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            let opts = ProposedOpts {
+                contributions: true,
+                ..Default::default()
+            };
+            let r = proposed_scheme(&world, &TupleCount, &opts).unwrap();
+            for (seller, v) in &r.shapley_values {
+                let tables = &r.contributions[seller];
+                let actual = tables.values().map(|t| t.value).sum::<f64>();
+                assert!((actual - v).abs() < 1e-5);
+                for (table, contribution) in tables {
+                    let actual = contribution.rows.values().sum::<f64>();
+                    assert!((actual - contribution.value).abs() < 1e-5);
+                    // only rows the seller owns earn it value.
+                    for row_id in contribution.rows.keys() {
+                        assert!(world.tables[table].seller_map[row_id].contains(seller));
+                    }
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            // a few hundred joined tuples at a time.
+            let opts = ProposedOpts {
+                memory_limit: Some(1 << 16),
+                interactions: true,
+                ..Default::default()
+            };
+            let r = proposed_scheme(&world, &TupleCount, &opts).unwrap();
             assert_world_sv(&r.shapley_values);
+            let expected = proposed_scheme(
+                &world,
+                &TupleCount,
+                &ProposedOpts {
+                    interactions: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(r.distinct_syntheses, expected.distinct_syntheses);
+            for ((i, j, v), (i_e, j_e, v_e)) in r.interactions.iter().zip(&expected.interactions) {
+                assert_eq!((i, j), (i_e, j_e));
+                assert!((v - v_e).abs() < 1e-5);
+            }
+
+            // rooted at country, city is the largest table and gets chunked instead.
+            let plan = JoinPlan::new(
+                "country",
+                vec![
+                    JoinStep::new("city", &["Code"], &["CountryCode"]).with_left_table("country"),
+                    JoinStep::new("countrylanguage", &["Code"], &["CountryCode"])
+                        .with_join_type(JoinKind::Left),
+                ],
+            );
+            assert_eq!(plan.inner_tables(), HashSet::from(["country", "city"]));
+            let world = world.with_plan(plan);
+            let r = proposed_scheme(&world, &TupleCount, &opts).unwrap();
+            let expected = proposed_scheme(&world, &TupleCount, &Default::default()).unwrap();
+            for (seller, v) in &expected.shapley_values {
+                assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_roster() {
+        polars_core::POOL.install(|| {
+            let mut world = load_world();
+            // a declared seller owning no rows.
+            let idle = world.registry.register(SellerInfo::new("idle")).unwrap();
+            world.sellers = world.registry.sellers();
+            let r = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap();
+            assert_eq!(r.shapley_values[&idle], 0.);
+            let expected = traditional_scheme(&world, &TupleCount, &Semivalue::Shapley).unwrap();
+            assert_eq!(r.shapley_values.len(), expected.shapley_values.len());
+            for (seller, v) in &expected.shapley_values {
+                assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_joint() {
+        polars_core::POOL.install(|| {
+            let mut world = load_world();
+            // all owners of some countries must take part.
+            let country = world.tables.get_mut("country").unwrap();
+            country.joint_rows = country
+                .seller_map
+                .iter()
+                .filter(|(row_id, sellers)| sellers.len() > 1 && row_id.0 % 2 == 0)
+                .map(|(row_id, _)| *row_id)
+                .collect();
+            assert!(!country.joint_rows.is_empty());
+            let r = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap();
+            let expected = traditional_scheme(&world, &TupleCount, &Semivalue::Shapley).unwrap();
+            for (seller, v) in &expected.shapley_values {
+                assert!((r.shapley_values[seller] - v).abs() < 1e-5);
+            }
+        });
+    }
+
+    #[test]
+    fn test_unowned_row() {
+        polars_core::POOL.install(|| {
+            let mut world = load_world();
+            let country = world.tables.get_mut("country").unwrap();
+            let row_id = *country.seller_map.keys().min().unwrap();
+            country.seller_map.remove(&row_id);
+            let err = proposed_scheme(&world, &TupleCount, &ProposedOpts::default()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("row id {} of country has no owner", row_id)
+            );
         });
     }
 }
@@ -0,0 +1,94 @@
+use crate::{RowId, SellerId, SellerRegistry, ROW_ID_COL_NAME};
+use anyhow::{Context, Result};
+use polars::prelude::*;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Streams the split of every unit among its sellers, one chunk of units at a time, as rows
+/// of `(tuple, _row_id:<table>..., seller, value)`, with sellers by their external names.
+///
+/// A `.csv` path gets a single csv file. Any other path is a directory of parquet files, one
+/// per chunk.
+pub struct AttributionWriter {
+    path: PathBuf,
+    csv: Option<BufWriter<File>>,
+    tables: Vec<String>,
+    chunks: usize,
+}
+
+impl AttributionWriter {
+    pub fn new(path: &Path, tables: Vec<String>) -> Result<Self> {
+        let csv = if path.extension().is_some_and(|ext| ext == "csv") {
+            let file = File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            Some(BufWriter::new(file))
+        } else {
+            fs::create_dir_all(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            None
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            csv,
+            tables,
+            chunks: 0,
+        })
+    }
+
+    /// Write the values of a chunk of units, each with the row ids of one of its tuples.
+    pub fn write<'a>(
+        &mut self,
+        registry: &SellerRegistry,
+        units: impl Iterator<Item = (usize, Vec<Option<RowId>>, &'a HashMap<SellerId, f64>)>,
+    ) -> Result<()> {
+        let mut tuple_col = vec![];
+        let mut row_id_cols = vec![vec![]; self.tables.len()];
+        let mut seller_col = vec![];
+        let mut value_col = vec![];
+        for (tuple, row_ids, sv) in units {
+            let mut sv: Vec<_> = sv.iter().collect();
+            sv.sort_by_key(|(seller, _)| **seller);
+            for (seller, value) in sv {
+                tuple_col.push(tuple as u64);
+                for (col, row_id) in row_id_cols.iter_mut().zip(&row_ids) {
+                    col.push(row_id.map(|row_id| row_id.0));
+                }
+                seller_col.push(registry.name(*seller));
+                value_col.push(*value);
+            }
+        }
+
+        let mut columns = vec![Series::new("tuple", tuple_col)];
+        for (table, col) in self.tables.iter().zip(row_id_cols) {
+            columns.push(Series::new(&format!("{}:{}", ROW_ID_COL_NAME, table), col));
+        }
+        columns.push(Series::new("seller", seller_col));
+        columns.push(Series::new("value", value_col));
+        let df = DataFrame::new(columns)?;
+
+        match &mut self.csv {
+            Some(out) => CsvWriter::new(out)
+                .has_header(self.chunks == 0)
+                .finish(&df)?,
+            None => {
+                let path = self.path.join(format!("part-{:05}.parquet", self.chunks));
+                let file = File::create(&path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                ParquetWriter::new(file).finish(&df)?;
+            }
+        }
+        self.chunks += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(out) = &mut self.csv {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
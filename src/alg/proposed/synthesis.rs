@@ -1,6 +1,6 @@
-use crate::{SellerId, SellerSet};
+use crate::{RowOwners, SellerBits, SellerId, SellerIndex, SellerSet, WideBits};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 #[derive(
     Debug,
@@ -23,16 +23,27 @@ use std::collections::HashSet;
 pub struct Synthesis(pub HashSet<SellerSet>);
 
 impl Synthesis {
-    pub fn from_seller_sets<'a>(input: impl 'a + Iterator<Item = &'a SellerSet>) -> Self {
+    /// Build the synthesis of a joined tuple from the owners of the rows it is built from: any
+    /// one owner of a row supplies it, unless they own it jointly and all of them must.
+    ///
+    /// Tables that contribute no row to a null-extended tuple must be left out of `input`,
+    /// as they put no condition on who can supply the tuple.
+    pub fn from_seller_sets<'a>(input: impl 'a + Iterator<Item = RowOwners<'a>>) -> Self {
         let mut ans = Self::default();
         ans.insert(SellerSet::default());
-        for set in input {
+        for owners in input {
             let mut new_ans = Self::default();
             for old_s in ans.iter() {
-                for seller in set.iter().copied() {
+                if owners.joint {
                     let mut s = old_s.clone();
-                    s.insert(seller);
+                    s.extend(owners.sellers.iter().copied());
                     new_ans.insert(s);
+                } else {
+                    for seller in owners.sellers.iter().copied() {
+                        let mut s = old_s.clone();
+                        s.insert(seller);
+                        new_ans.insert(s);
+                    }
                 }
             }
             ans = new_ans;
@@ -40,9 +51,24 @@ impl Synthesis {
         ans
     }
 
+    /// Merge the syntheses of tuples that are the same answer tuple: supplying any one of
+    /// them is enough. Call [`Synthesis::minimal`] afterwards.
+    pub fn union(&mut self, other: Synthesis) {
+        self.extend(other.0);
+    }
+
     pub fn minimal(&mut self) {
-        let mut sets: Vec<_> = self.drain().collect();
-        sets.sort_unstable_by_key(|s| s.len());
+        let index = SellerIndex::new(self.unique_sellers());
+        match index.len() {
+            0..=64 => self.minimal_bits::<u64>(&index),
+            65..=128 => self.minimal_bits::<u128>(&index),
+            _ => self.minimal_bits::<WideBits>(&index),
+        }
+    }
+
+    fn minimal_bits<B: SellerBits>(&mut self, index: &SellerIndex) {
+        let mut sets: Vec<(B, SellerSet)> = self.drain().map(|s| (index.encode(&s), s)).collect();
+        sets.sort_unstable_by_key(|(_, s)| s.len());
         let mut skips = vec![false; sets.len()];
 
         for i in 0..sets.len() {
@@ -55,13 +81,13 @@ impl Synthesis {
                     continue;
                 }
 
-                if sets[i].is_subset(&sets[j]) {
+                if sets[i].0.is_subset(&sets[j].0) {
                     skips[j] = true;
                 }
             }
         }
 
-        for (i, s) in sets.into_iter().enumerate() {
+        for (i, (_, s)) in sets.into_iter().enumerate() {
             if !skips[i] {
                 self.insert(s);
             }
@@ -93,27 +119,94 @@ impl Synthesis {
         }
         sellers
     }
+
+    /// Return the sets, sorted, and the sellers in the order of their labels.
+    ///
+    /// With `symmetric`, sellers are relabeled `0..n`, ordered by the sizes of the sets they
+    /// are in before their ids, so that syntheses equal up to renaming sellers mostly share the
+    /// same form. Otherwise sellers keep their ids, and only identical syntheses do.
+    pub fn canonical(&self, symmetric: bool) -> (Vec<SellerSet>, Vec<SellerId>) {
+        let mut sellers: Vec<(Vec<usize>, SellerId)> = self
+            .unique_sellers()
+            .into_iter()
+            .map(|seller| {
+                let mut sizes = vec![];
+                if symmetric {
+                    sizes.extend(self.iter().filter(|s| s.contains(&seller)).map(|s| s.len()));
+                    sizes.sort_unstable();
+                }
+                (sizes, seller)
+            })
+            .collect();
+        sellers.sort();
+        let labels: HashMap<SellerId, u64> = sellers
+            .iter()
+            .enumerate()
+            .map(|(label, (_, seller))| {
+                let label = if symmetric { label as u64 } else { seller.0 };
+                (*seller, label)
+            })
+            .collect();
+        let mut sets: Vec<SellerSet> = self
+            .iter()
+            .map(|set| set.iter().map(|seller| labels[seller]).collect())
+            .collect();
+        sets.sort();
+        (
+            sets,
+            sellers.into_iter().map(|(_, seller)| seller).collect(),
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn copies<'a>(sets: &'a [SellerSet]) -> impl 'a + Iterator<Item = RowOwners<'a>> {
+        sets.iter().map(|sellers| RowOwners {
+            sellers,
+            joint: false,
+        })
+    }
+
     #[test]
     fn test_from_seller_set() {
+        let sets = [
+            vec![1, 2].into_iter().collect::<SellerSet>(),
+            vec![1, 3].into_iter().collect::<SellerSet>(),
+        ];
+        let actual = Synthesis::from_seller_sets(copies(&sets));
+        let expect = Synthesis::new(
+            vec![
+                vec![1].into_iter().collect::<SellerSet>(),
+                vec![1, 2].into_iter().collect::<SellerSet>(),
+                vec![1, 3].into_iter().collect::<SellerSet>(),
+                vec![2, 3].into_iter().collect::<SellerSet>(),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(actual, expect);
+
+        // the first row needs both 1 and 2.
         let actual = Synthesis::from_seller_sets(
             vec![
-                &vec![1, 2].into_iter().collect::<SellerSet>(),
-                &vec![1, 3].into_iter().collect::<SellerSet>(),
+                RowOwners {
+                    sellers: &sets[0],
+                    joint: true,
+                },
+                RowOwners {
+                    sellers: &sets[1],
+                    joint: false,
+                },
             ]
             .into_iter(),
         );
         let expect = Synthesis::new(
             vec![
-                vec![1].into_iter().collect::<SellerSet>(),
                 vec![1, 2].into_iter().collect::<SellerSet>(),
-                vec![1, 3].into_iter().collect::<SellerSet>(),
-                vec![2, 3].into_iter().collect::<SellerSet>(),
+                vec![1, 2, 3].into_iter().collect::<SellerSet>(),
             ]
             .into_iter()
             .collect(),
@@ -121,6 +214,28 @@ mod tests {
         assert_eq!(actual, expect);
     }
 
+    #[test]
+    fn test_union() {
+        let mut syn = Synthesis::from_seller_sets(copies(&[
+            vec![1].into_iter().collect::<SellerSet>(),
+            vec![2].into_iter().collect::<SellerSet>(),
+        ]));
+        syn.union(Synthesis::from_seller_sets(copies(&[
+            vec![1].into_iter().collect::<SellerSet>(),
+            vec![2, 3].into_iter().collect::<SellerSet>(),
+        ])));
+        syn.minimal();
+        let expect = Synthesis::new(
+            vec![
+                vec![1, 2].into_iter().collect::<SellerSet>(),
+                vec![1, 3].into_iter().collect::<SellerSet>(),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(syn, expect);
+    }
+
     #[test]
     fn test_minimal() {
         let mut syn = Synthesis::new(
@@ -152,4 +267,32 @@ mod tests {
         );
         assert_eq!(syn, expect);
     }
+
+    #[test]
+    fn test_canonical() {
+        let a = Synthesis::new(
+            vec![
+                vec![7].into_iter().collect::<SellerSet>(),
+                vec![2, 5].into_iter().collect::<SellerSet>(),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let b = Synthesis::new(
+            vec![
+                vec![1].into_iter().collect::<SellerSet>(),
+                vec![3, 9].into_iter().collect::<SellerSet>(),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let (sets, sellers) = a.canonical(true);
+        assert_eq!(sets, b.canonical(true).0);
+        assert_eq!(sellers, vec![SellerId(7), SellerId(2), SellerId(5)]);
+        assert_ne!(a.canonical(false).0, b.canonical(false).0);
+        assert_eq!(
+            a.canonical(false).1,
+            vec![SellerId(2), SellerId(5), SellerId(7)]
+        );
+    }
 }
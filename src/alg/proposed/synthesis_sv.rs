@@ -1,7 +1,8 @@
 use super::*;
 use crate::{
+    alg::{semivalue::shapley_weight, weighted_integral},
     utils::{binom, merge_sv},
-    SellerId,
+    SellerBits, SellerId, SellerIndex, SellerStructure, SellerWeights, Semivalue, WideBits,
 };
 use std::collections::HashMap;
 
@@ -35,12 +36,130 @@ pub fn cal_sv_linear(syns: &Synthesis, count: usize, k: usize) -> HashMap<Seller
     ans
 }
 
+/// Closed form of the weighted Shapley value of a linear synthesis. Members of the
+/// multi-seller set `A` are pivotal iff they arrive last of `A` and before every singleton;
+/// a singleton is pivotal iff it arrives before the other singletons and the last of `A`.
+pub fn cal_weighted_sv_linear(syns: &Synthesis, weights: &SellerWeights) -> HashMap<SellerId, f64> {
+    let mut multi = None;
+    let mut singletons = vec![];
+    for syn in syns.iter() {
+        if syn.len() == 1 {
+            singletons.push(*syn.iter().next().unwrap());
+        } else {
+            multi = Some(syn);
+        }
+    }
+    let multi_weight = multi.map(|syn| syn.iter().map(|id| weights.get(*id)).sum::<f64>());
+
+    let mut ans = HashMap::new();
+    if let (Some(syn), Some(multi_weight)) = (multi, multi_weight) {
+        let u = weighted_integral(multi_weight, singletons.iter().map(|id| weights.get(*id)));
+        for id in syn.iter() {
+            ans.insert(*id, weights.get(*id) * u);
+        }
+    }
+    for &id in &singletons {
+        let w = weights.get(id);
+        let others = singletons
+            .iter()
+            .filter(|other| **other != id)
+            .map(|other| weights.get(*other))
+            .chain(multi_weight);
+        ans.insert(id, w * weighted_integral(w, others));
+    }
+    ans
+}
+
+/// How the non linear kernels split a unit among the sellers of its synthesis.
+pub enum Weighting<'a> {
+    /// A semivalue on the sellers of the synthesis.
+    Semivalue {
+        size_weights: Vec<f64>,
+        unanimity_values: Vec<f64>,
+    },
+    /// Weighted Shapley or Owen value.
+    Structure(SellerStructure<'a>),
+}
+
+impl<'a> Weighting<'a> {
+    fn new(value: &Semivalue, structure: Option<SellerStructure<'a>>, n: usize) -> Self {
+        match structure {
+            Some(structure) => Self::Structure(structure),
+            None => Self::Semivalue {
+                size_weights: value.size_weights(n),
+                unanimity_values: value.unanimity_values(n),
+            },
+        }
+    }
+
+    /// Weight of the marginal contribution of the seller at `seller` to `subset`, which does
+    /// not contain it.
+    pub fn coalition_weight<B: SellerBits>(
+        &self,
+        seller: usize,
+        subset: &B,
+        index: &SellerIndex,
+    ) -> f64 {
+        match self {
+            Self::Semivalue { size_weights, .. } => {
+                size_weights[subset.len()] / binom(subset.len(), index.len() - 1) as f64
+            }
+            Self::Structure(structure) => structure.coalition_weight(
+                index.id(seller),
+                subset.ones().map(|i| index.id(i)),
+                (0..index.len())
+                    .filter(|&i| i != seller && !subset.contains(i))
+                    .map(|i| index.id(i)),
+            ),
+        }
+    }
+
+    /// Value of the seller at `seller` in the unanimity game on `set`, which contains it.
+    pub fn unanimity_value<B: SellerBits>(
+        &self,
+        seller: usize,
+        set: &B,
+        index: &SellerIndex,
+    ) -> f64 {
+        match self {
+            Self::Semivalue {
+                unanimity_values, ..
+            } => unanimity_values[set.len()],
+            Self::Structure(structure) => {
+                structure.unanimity_value(index.id(seller), set.ones().map(|i| index.id(i)))
+            }
+        }
+    }
+}
+
 /// Return (shapley_value, lookup_count, comb_count).
-pub fn cal_sv_non_linear(syns: &Synthesis, scale: f64) -> (HashMap<SellerId, f64>, usize, usize) {
-    let sellers = syns.unique_sellers();
-    sellers
-        .par_iter()
-        .map(|&seller| {
+///
+/// Values without a closed form for linear syntheses also go through here.
+pub fn cal_sv_non_linear(
+    syns: &Synthesis,
+    value: &Semivalue,
+    structure: Option<SellerStructure>,
+    scale: f64,
+) -> (HashMap<SellerId, f64>, usize, usize) {
+    let index = SellerIndex::new(syns.unique_sellers());
+    let weighting = Weighting::new(value, structure, index.len());
+    match index.len() {
+        0..=64 => cal_sv_non_linear_bits::<u64>(syns, &index, &weighting, scale),
+        65..=128 => cal_sv_non_linear_bits::<u128>(syns, &index, &weighting, scale),
+        _ => cal_sv_non_linear_bits::<WideBits>(syns, &index, &weighting, scale),
+    }
+}
+
+fn cal_sv_non_linear_bits<B: SellerBits>(
+    syns: &Synthesis,
+    index: &SellerIndex,
+    weighting: &Weighting,
+    scale: f64,
+) -> (HashMap<SellerId, f64>, usize, usize) {
+    let syns: Vec<B> = syns.iter().map(|syn| index.encode(syn)).collect();
+    (0..index.len())
+        .into_par_iter()
+        .map(|seller| {
             let mut syns_with_current_seller = vec![];
             let mut syns_without_current_seller = vec![];
 
@@ -48,7 +167,7 @@ pub fn cal_sv_non_linear(syns: &Synthesis, scale: f64) -> (HashMap<SellerId, f64
             let mut lookup_count = 0;
             let mut comb_count = 0;
             for syn in syns.iter() {
-                if syn.contains(&seller) {
+                if syn.contains(seller) {
                     syns_with_current_seller.push(syn);
                 } else {
                     syns_without_current_seller.push(syn);
@@ -63,21 +182,25 @@ pub fn cal_sv_non_linear(syns: &Synthesis, scale: f64) -> (HashMap<SellerId, f64
                 syns_with_current_seller.len() * syns_without_current_seller.len()
             };
 
-            if sellers.len() as f64 <= scale * number_of_pow_for_syns as f64 {
+            if index.len() as f64 <= scale * number_of_pow_for_syns as f64 {
                 let u = non_linear_lookup::cal_sv_non_linear_lookup(
                     &syns_with_current_seller,
                     &syns_without_current_seller,
-                    &sellers,
                     seller,
+                    index,
+                    weighting,
                 );
-                ans.insert(seller, u);
+                ans.insert(index.id(seller), u);
                 lookup_count += 1;
             } else {
                 let u = non_linear_comb::cal_sv_non_linear_comb(
                     &syns_with_current_seller,
                     &syns_without_current_seller,
+                    seller,
+                    index,
+                    weighting,
                 );
-                ans.insert(seller, u);
+                ans.insert(index.id(seller), u);
                 comb_count += 1;
             }
             (ans, lookup_count, comb_count)
@@ -88,6 +211,65 @@ pub fn cal_sv_non_linear(syns: &Synthesis, scale: f64) -> (HashMap<SellerId, f64
         )
 }
 
+/// Interaction indices of seller pairs `(i, j)` with `i < j`.
+pub type Interactions = HashMap<(SellerId, SellerId), f64>;
+
+/// Syntheses over more sellers are too large to enumerate for interactions.
+pub const MAX_INTERACTION_SELLERS: usize = 20;
+
+/// Shapley interaction index of every pair of sellers of a synthesis, keyed `(i, j)` with
+/// `i < j`. Sellers of the same minimal set tend to be complementary (positive), and sellers
+/// of alternative minimal sets substitutes (negative).
+///
+/// Enumerates all coalitions of the sellers of the synthesis, as bitmasks, so `None` if it has
+/// more than [`MAX_INTERACTION_SELLERS`] sellers.
+pub fn cal_interactions(syns: &Synthesis) -> Option<Interactions> {
+    let mut sellers: Vec<SellerId> = syns.unique_sellers().into_iter().collect();
+    sellers.sort();
+    let n = sellers.len();
+    if n > MAX_INTERACTION_SELLERS {
+        return None;
+    }
+    if n < 2 {
+        return Some(HashMap::new());
+    }
+
+    let masks: Vec<u32> = syns
+        .iter()
+        .map(|syn| {
+            syn.iter()
+                .map(|s| 1 << sellers.binary_search(s).unwrap())
+                .fold(0, |a, b| a | b)
+        })
+        .collect();
+    let utility: Vec<i32> = (0..1u32 << n)
+        .map(|s| masks.iter().any(|m| s & m == *m) as i32)
+        .collect();
+    // the pair acts as a single player among n - 1.
+    let weights: Vec<f64> = (0..n - 1).map(|k| shapley_weight(k, n - 1)).collect();
+
+    let mut ans = HashMap::new();
+    for i in 0..n {
+        for j in i + 1..n {
+            let pair = 1 << i | 1 << j;
+            let mut v = 0.;
+            for s in (0..1u32 << n).filter(|s| s & pair == 0) {
+                let delta = utility[(s | pair) as usize]
+                    - utility[(s | 1 << i) as usize]
+                    - utility[(s | 1 << j) as usize]
+                    + utility[s as usize];
+                if delta != 0 {
+                    v += delta as f64 * weights[s.count_ones() as usize];
+                }
+            }
+            if v != 0. {
+                ans.insert((sellers[i], sellers[j]), v);
+            }
+        }
+    }
+    Some(ans)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,4 +299,28 @@ mod tests {
         );
         dbg!(cal_sv_linear(&syns, 0, 0));
     }
+
+    #[test]
+    fn test_interactions() {
+        let syns = Synthesis::new(
+            vec![
+                vec![0].into_iter().collect::<SellerSet>(),
+                vec![1, 2].into_iter().collect::<SellerSet>(),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let ans = cal_interactions(&syns).unwrap();
+        assert_eq!(ans.len(), 3);
+        assert!((ans[&(SellerId(1), SellerId(2))] - 0.5).abs() < 1e-9);
+        assert!((ans[&(SellerId(0), SellerId(1))] + 0.5).abs() < 1e-9);
+        assert!((ans[&(SellerId(0), SellerId(2))] + 0.5).abs() < 1e-9);
+
+        let syns = Synthesis::new(
+            (0..=MAX_INTERACTION_SELLERS as u64)
+                .map(|i| vec![i].into_iter().collect::<SellerSet>())
+                .collect(),
+        );
+        assert!(cal_interactions(&syns).is_none());
+    }
 }
@@ -1,53 +1,71 @@
-use crate::{SellerId, SellerSet};
+use super::Weighting;
+use crate::{SellerBits, SellerIndex};
 use rayon::prelude::*;
 use std::collections::HashSet;
 
-struct Union {
+struct Union<B> {
     num_of_set: usize,
     max_set_id: usize,
-    set: HashSet<SellerId>,
+    set: B,
 }
 
-impl Union {
+impl<B: SellerBits> Union<B> {
     #[inline(always)]
-    fn utility(&self) -> f64 {
-        let signed_flag = if self.num_of_set % 2 == 0 { -1. } else { 1. };
-        signed_flag / self.set.len() as f64
+    fn utility(&self, seller: usize, index: &SellerIndex, weighting: &Weighting) -> f64 {
+        let signed_flag = if self.num_of_set.is_multiple_of(2) {
+            -1.
+        } else {
+            1.
+        };
+        signed_flag * weighting.unanimity_value(seller, &self.set, index)
     }
 }
 
-fn get_utility_of_cardinality_of_set_union(syns: &[&SellerSet]) -> f64 {
+/// Inclusion–exclusion over the unanimity games on unions of `syns`, which all contain
+/// `seller`.
+fn get_utility_of_cardinality_of_set_union<B: SellerBits>(
+    syns: &[&B],
+    seller: usize,
+    index: &SellerIndex,
+    weighting: &Weighting,
+) -> f64 {
     let syns_len = syns.len();
     match syns_len {
         0 => return 0.,
-        1 => return 1. / syns[0].len() as f64,
+        1 => return weighting.unanimity_value(seller, syns[0], index),
         2 => {
-            return 1. / syns[0].len() as f64 + 1. / syns[1].len() as f64
-                - 1. / (syns[0].union(syns[1]).count() as f64);
+            let mut union = syns[0].clone();
+            union.union_with(syns[1]);
+            return weighting.unanimity_value(seller, syns[0], index)
+                + weighting.unanimity_value(seller, syns[1], index)
+                - weighting.unanimity_value(seller, &union, index);
         }
         _ => {}
     }
 
-    let mut unions: Vec<Union> = syns
+    let mut unions: Vec<Union<B>> = syns
         .into_par_iter()
         .enumerate()
         .map(|(set_id, set)| Union {
             num_of_set: 1,
             max_set_id: set_id,
-            set: set.iter().copied().collect(),
+            set: (*set).clone(),
         })
         .collect();
-    let mut ans = unions.par_iter().map(|u| u.utility()).sum();
+    let mut ans = unions
+        .par_iter()
+        .map(|u| u.utility(seller, index, weighting))
+        .sum();
 
     while !unions.is_empty() {
-        let new_unions: Vec<Union> = unions
+        let new_unions: Vec<Union<B>> = unions
             .par_iter()
             .flat_map(|old_u| {
                 (old_u.max_set_id + 1..syns_len)
                     .into_par_iter()
                     .map(|new_set_id| {
                         let mut new_set = old_u.set.clone();
-                        new_set.extend(syns[new_set_id].iter().copied());
+                        new_set.union_with(syns[new_set_id]);
                         Union {
                             num_of_set: old_u.num_of_set + 1,
                             max_set_id: new_set_id,
@@ -57,36 +75,41 @@ fn get_utility_of_cardinality_of_set_union(syns: &[&SellerSet]) -> f64 {
             })
             .collect();
 
-        ans += new_unions.par_iter().map(|u| u.utility()).sum::<f64>();
+        ans += new_unions
+            .par_iter()
+            .map(|u| u.utility(seller, index, weighting))
+            .sum::<f64>();
         unions = new_unions;
     }
 
     ans
 }
 
-pub fn cal_sv_non_linear_comb(
-    syns_with_current_seller: &[&SellerSet],
-    syns_without_current_seller: &[&SellerSet],
+pub fn cal_sv_non_linear_comb<B: SellerBits>(
+    syns_with_current_seller: &[&B],
+    syns_without_current_seller: &[&B],
+    seller: usize,
+    index: &SellerIndex,
+    weighting: &Weighting,
 ) -> f64 {
     let utility_with_current_seller =
-        get_utility_of_cardinality_of_set_union(syns_with_current_seller);
+        get_utility_of_cardinality_of_set_union(syns_with_current_seller, seller, index, weighting);
 
-    let syns_interaction_list: HashSet<SellerSet> = syns_with_current_seller
+    let syns_interaction_list: HashSet<B> = syns_with_current_seller
         .par_iter()
         .flat_map(|syn_with_current_seller| {
             syns_without_current_seller
                 .par_iter()
                 .map(|syn_without_current_seller| {
-                    syn_with_current_seller
-                        .union(syn_without_current_seller)
-                        .copied()
-                        .collect::<SellerSet>()
+                    let mut union = (*syn_with_current_seller).clone();
+                    union.union_with(syn_without_current_seller);
+                    union
                 })
         })
         .collect();
     let syns_interaction_list: Vec<_> = syns_interaction_list.iter().collect();
     let utility_without_current_seller =
-        get_utility_of_cardinality_of_set_union(&syns_interaction_list);
+        get_utility_of_cardinality_of_set_union(&syns_interaction_list, seller, index, weighting);
 
     utility_with_current_seller - utility_without_current_seller
 }
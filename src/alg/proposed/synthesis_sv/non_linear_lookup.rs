@@ -1,116 +1,111 @@
-use crate::{utils::binom, SellerId, SellerSet};
+use super::Weighting;
+use crate::{SellerBits, SellerIndex};
 use rayon::prelude::*;
-use std::collections::HashSet;
 
 #[derive(Clone)]
-struct Subset {
+struct Subset<B> {
     next_id: usize,
-    subset: HashSet<SellerId>,
+    subset: B,
     with_flag: bool,
 }
 
-impl Subset {
+impl<B: SellerBits> Subset<B> {
     fn utility_with_current_seller(
         &mut self,
-        seller: SellerId,
-        syns_with_current_seller: &[&SellerSet],
+        seller: usize,
+        syns_with_current_seller: &[&B],
     ) -> bool {
         if self.with_flag {
             return true;
         }
 
-        let syn_len_upper_bound = self.subset.len() + 1;
-        self.with_flag = syns_with_current_seller.par_iter().any(|syn| {
-            syn.len() <= syn_len_upper_bound
-                && syn
-                    .par_iter()
-                    .all(|v| *v == seller || self.subset.contains(v))
-        });
+        let mut coalition = self.subset.clone();
+        coalition.insert(seller);
+        self.with_flag = syns_with_current_seller
+            .iter()
+            .any(|syn| syn.is_subset(&coalition));
         self.with_flag
     }
 
-    fn utility_without_current_seller(&self, syns_without_current_seller: &[&SellerSet]) -> bool {
-        let syn_len_upper_bound = self.subset.len();
-        syns_without_current_seller.par_iter().any(|syn| {
-            syn.len() <= syn_len_upper_bound && syn.par_iter().all(|v| self.subset.contains(v))
-        })
+    fn utility_without_current_seller(&self, syns_without_current_seller: &[&B]) -> bool {
+        syns_without_current_seller
+            .iter()
+            .any(|syn| syn.is_subset(&self.subset))
     }
 }
 
-pub fn cal_sv_non_linear_lookup(
-    syns_with_current_seller: &[&SellerSet],
-    syns_without_current_seller: &[&SellerSet],
-    sellers: &HashSet<SellerId>,
-    seller: SellerId,
+pub fn cal_sv_non_linear_lookup<B: SellerBits>(
+    syns_with_current_seller: &[&B],
+    syns_without_current_seller: &[&B],
+    seller: usize,
+    index: &SellerIndex,
+    weighting: &Weighting,
 ) -> f64 {
-    let number_of_sellers = sellers.len();
-    let rest_of_sellers: Vec<_> = sellers.iter().copied().filter(|s| *s != seller).collect();
+    let rest_of_sellers: Vec<_> = (0..index.len()).filter(|s| *s != seller).collect();
     let rest_of_sellers_len = rest_of_sellers.len();
 
     let mut marginal_contribution_for_current_seller = 0.;
     let mut init_subset = Subset {
         next_id: 0,
-        subset: HashSet::new(),
+        subset: B::empty(index.len()),
         with_flag: false,
     };
 
     if init_subset.utility_with_current_seller(seller, syns_with_current_seller) {
         // when subset is empty; number_of_sub_combination = 1 and without_flag = false
-        marginal_contribution_for_current_seller += 1.;
+        marginal_contribution_for_current_seller +=
+            weighting.coalition_weight(seller, &init_subset.subset, index);
     }
 
-    let mut subsets: Vec<Subset> = vec![init_subset];
-    let mut chosen = 1;
+    let mut subsets: Vec<Subset<B>> = vec![init_subset];
 
     while !subsets.is_empty() {
-        let (marginal_contribution_in_sub_combination, new_subsets): (usize, Vec<Subset>) = subsets
-            .par_iter()
-            .flat_map(|old_s| {
-                (old_s.next_id..rest_of_sellers_len)
-                    .into_par_iter()
-                    .filter_map(|next_id| {
-                        let mut new_s = old_s.clone();
-                        new_s.next_id = next_id + 1;
-                        new_s.subset.insert(rest_of_sellers[next_id]);
+        let (marginal_contribution_in_sub_combination, new_subsets): (f64, Vec<Subset<B>>) =
+            subsets
+                .par_iter()
+                .flat_map(|old_s| {
+                    (old_s.next_id..rest_of_sellers_len)
+                        .into_par_iter()
+                        .filter_map(|next_id| {
+                            let mut new_s = old_s.clone();
+                            new_s.next_id = next_id + 1;
+                            new_s.subset.insert(rest_of_sellers[next_id]);
 
-                        if new_s.utility_with_current_seller(seller, syns_with_current_seller) {
-                            if new_s.utility_without_current_seller(syns_without_current_seller) {
-                                // early stop
-                                return None;
-                            } else {
-                                return Some((1, new_s));
+                            if new_s.utility_with_current_seller(seller, syns_with_current_seller) {
+                                if new_s.utility_without_current_seller(syns_without_current_seller)
+                                {
+                                    // early stop
+                                    return None;
+                                } else {
+                                    let w =
+                                        weighting.coalition_weight(seller, &new_s.subset, index);
+                                    return Some((w, new_s));
+                                }
                             }
-                        }
 
-                        Some((0, new_s))
-                    })
-            })
-            .fold(
-                || (0, Vec::new()),
-                |mut acc, input| {
-                    acc.0 += input.0;
-                    acc.1.push(input.1);
-                    acc
-                },
-            )
-            .reduce(
-                || (0, Vec::new()),
-                |mut a, mut b| -> (usize, Vec<Subset>) {
-                    a.0 += b.0;
-                    a.1.append(&mut b.1);
-                    a
-                },
-            );
-
-        if marginal_contribution_in_sub_combination != 0 {
-            let number_of_sub_combination = binom(chosen, rest_of_sellers_len);
-            marginal_contribution_for_current_seller +=
-                marginal_contribution_in_sub_combination as f64 / number_of_sub_combination as f64;
-        }
+                            Some((0., new_s))
+                        })
+                })
+                .fold(
+                    || (0., Vec::new()),
+                    |mut acc, input| {
+                        acc.0 += input.0;
+                        acc.1.push(input.1);
+                        acc
+                    },
+                )
+                .reduce(
+                    || (0., Vec::new()),
+                    |mut a, mut b| -> (f64, Vec<Subset<B>>) {
+                        a.0 += b.0;
+                        a.1.append(&mut b.1);
+                        a
+                    },
+                );
 
+        marginal_contribution_for_current_seller += marginal_contribution_in_sub_combination;
         subsets = new_subsets;
-        chosen += 1;
     }
 
-    marginal_contribution_for_current_seller / number_of_sellers as f64
+    marginal_contribution_for_current_seller
 }
@@ -1,18 +1,90 @@
-use crate::SellerId;
+use crate::{RowId, SellerId};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, time::Duration};
+use std::{collections::HashMap, hash::Hash, time::Duration};
 
+/// Values of sellers keyed by `K`: internal ids while computing, external names in outputs.
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct ShapleyResult {
+#[serde(bound(
+    serialize = "K: Serialize + Eq + Hash",
+    deserialize = "K: Deserialize<'de> + Eq + Hash"
+))]
+pub struct ShapleyResult<K = SellerId> {
     #[serde(with = "serde_time")]
     pub avg_time: Duration,
     #[serde(with = "serde_time")]
     pub total_time: Duration,
-    pub shapley_values: HashMap<SellerId, f64>,
+    pub shapley_values: HashMap<K, f64>,
     // following is only used by proposed.
     pub linear_count: usize,
     pub lookup_count: usize,
     pub comb_count: usize,
+    /// Syntheses solved after merging those equal up to renaming sellers.
+    #[serde(default)]
+    pub distinct_syntheses: usize,
+    /// Share of units whose synthesis was already solved.
+    #[serde(default)]
+    pub memo_hit_rate: f64,
+    // following is only used by sampling schemes.
+    #[serde(default)]
+    pub num_samples: usize,
+    #[serde(default)]
+    pub variances: HashMap<K, f64>,
+    /// 95% confidence intervals.
+    #[serde(default)]
+    pub confidence_intervals: HashMap<K, (f64, f64)>,
+    // following is only used with seller unions.
+    /// Values of the union-level game.
+    #[serde(default)]
+    pub union_values: HashMap<String, f64>,
+    // following is only used when computing interactions.
+    /// Shapley interaction index of seller pairs as sparse `(i, j, value)` triplets with
+    /// `i < j`, sorted.
+    #[serde(default)]
+    pub interactions: Vec<(K, K, f64)>,
+    // following is only used by proposed when breaking values down by source rows.
+    /// Value of every seller attributed back to the tables and rows it owns.
+    #[serde(default)]
+    pub contributions: HashMap<K, HashMap<String, TableContribution>>,
+}
+
+impl ShapleyResult {
+    /// Key the result by another name of the sellers, e.g. their external names.
+    pub fn map_sellers<K: Eq + Hash>(self, f: impl Fn(SellerId) -> K) -> ShapleyResult<K> {
+        ShapleyResult {
+            avg_time: self.avg_time,
+            total_time: self.total_time,
+            shapley_values: map_keys(self.shapley_values, &f),
+            linear_count: self.linear_count,
+            lookup_count: self.lookup_count,
+            comb_count: self.comb_count,
+            distinct_syntheses: self.distinct_syntheses,
+            memo_hit_rate: self.memo_hit_rate,
+            num_samples: self.num_samples,
+            variances: map_keys(self.variances, &f),
+            confidence_intervals: map_keys(self.confidence_intervals, &f),
+            union_values: self.union_values,
+            interactions: self
+                .interactions
+                .into_iter()
+                .map(|(i, j, v)| (f(i), f(j), v))
+                .collect(),
+            contributions: map_keys(self.contributions, &f),
+        }
+    }
+}
+
+fn map_keys<K: Eq + Hash, V>(
+    map: HashMap<SellerId, V>,
+    f: impl Fn(SellerId) -> K,
+) -> HashMap<K, V> {
+    map.into_iter().map(|(s, v)| (f(s), v)).collect()
+}
+
+/// Part of a seller's value earned by its rows of one table.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TableContribution {
+    pub value: f64,
+    pub rows: HashMap<RowId, f64>,
 }
 
 mod serde_time {
@@ -0,0 +1,431 @@
+use crate::{DataSet, SellerId, SellerUnions, SellerWeights, UnionId};
+use anyhow::{bail, ensure, Context, Error, Result};
+use rand::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+/// A semivalue: the value of a seller is a weighted sum of its marginal contributions, where
+/// the weight of a coalition only depends on its size.
+///
+/// All semivalues here are probabilistic (the weights of the coalitions of other sellers add
+/// up to 1) and keep the value of a seller unchanged when null sellers are added, so they can
+/// be computed on the sellers of each unit alone.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Semivalue {
+    #[default]
+    Shapley,
+    Banzhaf,
+    /// Beta Shapley value. A coalition of `s` out of the other `n - 1` sellers weighs
+    /// `B(s + beta, n - 1 - s + alpha) / B(alpha, beta)`, so `alpha > beta` emphasizes small
+    /// coalitions. `Beta(1, 1)` is the Shapley value.
+    Beta {
+        alpha: f64,
+        beta: f64,
+    },
+}
+
+impl Semivalue {
+    /// Total weight of the coalitions of each size `0..n` out of the other `n - 1` sellers.
+    ///
+    /// The value is `sum_k size_weights[k] * (mean marginal contribution to k-coalitions)`.
+    pub fn size_weights(&self, n: usize) -> Vec<f64> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let m = (n - 1) as f64;
+        // in log space, as the binomial coefficients overflow for many sellers.
+        let mut ln_weight = match *self {
+            Self::Shapley => return vec![1. / n as f64; n],
+            Self::Banzhaf => -m * 2f64.ln(),
+            Self::Beta { alpha, beta } => (0..n - 1)
+                .map(|i| ((alpha + i as f64) / (alpha + beta + i as f64)).ln())
+                .sum(),
+        };
+        let mut ans = Vec::with_capacity(n);
+        for s in 0..n {
+            ans.push(ln_weight.exp());
+            let s = s as f64;
+            ln_weight += ((m - s) / (s + 1.)).ln();
+            if let Self::Beta { alpha, beta } = *self {
+                ln_weight += ((s + beta) / (m - 1. - s + alpha)).ln();
+            }
+        }
+        ans
+    }
+
+    /// Value of each of the `t` members of a unanimity game on `t` sellers, for `t` in `0..=n`.
+    pub fn unanimity_values(&self, n: usize) -> Vec<f64> {
+        let mut ans = vec![0.; n + 1];
+        let mut v = 1.;
+        for (t, ans) in ans.iter_mut().enumerate().skip(1) {
+            *ans = v;
+            let t = t as f64;
+            v *= match *self {
+                Self::Shapley => t / (t + 1.),
+                Self::Banzhaf => 0.5,
+                Self::Beta { alpha, beta } => (beta + t - 1.) / (alpha + beta + t - 1.),
+            };
+        }
+        ans
+    }
+}
+
+impl FromStr for Semivalue {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+        Ok(match (kind, arg) {
+            ("shapley", "") => Self::Shapley,
+            ("banzhaf", "") => Self::Banzhaf,
+            ("beta", args) => {
+                let (alpha, beta) = args
+                    .split_once(',')
+                    .context("beta value needs two parameters: beta:<a>,<b>")?;
+                let (alpha, beta): (f64, f64) = (alpha.trim().parse()?, beta.trim().parse()?);
+                ensure!(alpha > 0. && beta > 0., "beta parameters must be positive");
+                Self::Beta { alpha, beta }
+            }
+            _ => bail!(
+                "Unknown value {}. accepted values: [shapley, banzhaf, beta:<a>,<b>]",
+                s
+            ),
+        })
+    }
+}
+
+impl fmt::Display for Semivalue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Shapley => write!(f, "shapley"),
+            Self::Banzhaf => write!(f, "banzhaf"),
+            Self::Beta { alpha, beta } => write!(f, "beta:{},{}", alpha, beta),
+        }
+    }
+}
+
+/// Asymmetric variants of the Shapley value, defined by their random orders of sellers.
+#[derive(Debug, Clone, Copy)]
+pub enum SellerStructure<'a> {
+    /// Weighted Shapley value.
+    Weights(&'a SellerWeights),
+    /// Owen value.
+    Unions(&'a SellerUnions),
+}
+
+impl<'a> SellerStructure<'a> {
+    /// Seller structure of `dataset`, which is only defined for the Shapley value.
+    pub fn of(dataset: &'a DataSet, value: &Semivalue) -> Result<Option<Self>> {
+        let ans = match (&dataset.seller_weights, &dataset.seller_unions) {
+            (None, None) => return Ok(None),
+            (Some(weights), None) => Self::Weights(weights),
+            (None, Some(unions)) => Self::Unions(unions),
+            (Some(_), Some(_)) => bail!("seller weights and unions cannot be combined"),
+        };
+        ensure!(
+            *value == Semivalue::Shapley,
+            "seller weights and unions only apply to the Shapley value, not {}",
+            value
+        );
+        Ok(Some(ans))
+    }
+
+    /// Probability that the sellers before `seller` are exactly `subset`, out of `subset`,
+    /// `rest` and `seller`.
+    pub fn coalition_weight(
+        &self,
+        seller: SellerId,
+        subset: impl IntoIterator<Item = SellerId>,
+        rest: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        match self {
+            Self::Weights(weights) => weights.coalition_weight(seller, subset, rest),
+            Self::Unions(unions) => unions.coalition_weight(seller, subset, rest),
+        }
+    }
+
+    /// Value of `seller` in the unanimity game on `set`, which contains it.
+    pub fn unanimity_value(
+        &self,
+        seller: SellerId,
+        set: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        match self {
+            Self::Weights(weights) => weights.unanimity_value(seller, set),
+            Self::Unions(unions) => unions.unanimity_value(seller, set),
+        }
+    }
+
+    /// Draw a random order of `sellers`.
+    pub fn shuffle(&self, sellers: &mut Vec<SellerId>, rng: &mut impl Rng) {
+        match self {
+            Self::Weights(weights) => weights.shuffle(sellers, rng),
+            Self::Unions(unions) => unions.shuffle(sellers, rng),
+        }
+    }
+}
+
+/// Weighted Shapley value, in the random order model: seller `j` arrives at time
+/// `U_j^(1 / w_j)`, so the last seller of any coalition is drawn proportionally to the
+/// weights.
+impl SellerWeights {
+    /// Probability that the sellers before `seller` are exactly `subset`, out of `subset`,
+    /// `rest` and `seller`.
+    pub fn coalition_weight(
+        &self,
+        seller: SellerId,
+        subset: impl IntoIterator<Item = SellerId>,
+        rest: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        let w = self.get(seller);
+        let base = w + subset.into_iter().map(|s| self.get(s)).sum::<f64>();
+        w * weighted_integral(base, rest.into_iter().map(|s| self.get(s)))
+    }
+
+    /// Value of `seller` in the unanimity game on `set`, which contains it.
+    pub fn unanimity_value(
+        &self,
+        seller: SellerId,
+        set: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        self.get(seller) / set.into_iter().map(|s| self.get(s)).sum::<f64>()
+    }
+
+    pub fn shuffle(&self, sellers: &mut Vec<SellerId>, rng: &mut impl Rng) {
+        let mut arrivals: Vec<(f64, SellerId)> = sellers
+            .drain(..)
+            .map(|s| (rng.gen::<f64>().powf(1. / self.get(s)), s))
+            .collect();
+        arrivals.sort_by(|a, b| a.0.total_cmp(&b.0));
+        sellers.extend(arrivals.into_iter().map(|(_, s)| s));
+    }
+}
+
+/// Owen value, in the random order model: unions arrive in a random order, and the sellers of
+/// each union in a random order right after one another.
+impl SellerUnions {
+    /// Probability that the sellers before `seller` are exactly `subset`, out of `subset`,
+    /// `rest` and `seller`.
+    pub fn coalition_weight(
+        &self,
+        seller: SellerId,
+        subset: impl IntoIterator<Item = SellerId>,
+        rest: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        let own = self.union_id(seller);
+        // (sellers in subset, sellers) of each union.
+        let mut unions: HashMap<UnionId, (usize, usize)> = HashMap::new();
+        unions.insert(own, (0, 1));
+        for s in subset {
+            let e = unions.entry(self.union_id(s)).or_default();
+            e.0 += 1;
+            e.1 += 1;
+        }
+        for s in rest {
+            unions.entry(self.union_id(s)).or_default().1 += 1;
+        }
+
+        let mut unions_before = 0;
+        for (id, (in_subset, total)) in &unions {
+            if *id == own || *in_subset == 0 {
+                continue;
+            }
+            if in_subset < total {
+                // other unions are never split.
+                return 0.;
+            }
+            unions_before += 1;
+        }
+        let (sellers_before, union_len) = unions[&own];
+        shapley_weight(unions_before, unions.len()) * shapley_weight(sellers_before, union_len)
+    }
+
+    /// Value of `seller` in the unanimity game on `set`, which contains it: split evenly
+    /// between the unions, then between their sellers in `set`.
+    pub fn unanimity_value(
+        &self,
+        seller: SellerId,
+        set: impl IntoIterator<Item = SellerId>,
+    ) -> f64 {
+        let own = self.union_id(seller);
+        let mut unions = HashSet::new();
+        let mut members = 0;
+        for s in set {
+            let id = self.union_id(s);
+            if id == own {
+                members += 1;
+            }
+            unions.insert(id);
+        }
+        1. / (unions.len() * members) as f64
+    }
+
+    pub fn shuffle(&self, sellers: &mut Vec<SellerId>, rng: &mut impl Rng) {
+        let mut unions: BTreeMap<UnionId, Vec<SellerId>> = BTreeMap::new();
+        for s in sellers.drain(..) {
+            unions.entry(self.union_id(s)).or_default().push(s);
+        }
+        let mut unions: Vec<Vec<SellerId>> = unions.into_values().collect();
+        unions.shuffle(rng);
+        for mut union in unions {
+            union.shuffle(rng);
+            sellers.extend(union);
+        }
+    }
+
+    /// Values of the union-level game, i.e. the sum of the Owen values of each union.
+    pub fn union_values(&self, values: &HashMap<SellerId, f64>) -> HashMap<String, f64> {
+        let mut ans = HashMap::new();
+        for (seller, v) in values {
+            *ans.entry(self.union_name(*seller)).or_default() += v;
+        }
+        ans
+    }
+}
+
+/// Weight of a `k`-coalition of the other `n - 1` players under the Shapley value,
+/// `k! (n - 1 - k)! / n!`.
+pub(crate) fn shapley_weight(k: usize, n: usize) -> f64 {
+    (1..=k)
+        .map(|i| i as f64 / (n - k + i) as f64)
+        .product::<f64>()
+        / (n - k) as f64
+}
+
+/// `∫_0^1 t^(base - 1) Π_j (1 - t^w_j) dt` over `weights`: the probability that none of them
+/// arrived by the time a seller of weight `base` does, divided by `base`.
+pub fn weighted_integral(base: f64, weights: impl IntoIterator<Item = f64>) -> f64 {
+    // sellers of equal weight are interchangeable. expand all groups but the largest by
+    // inclusion–exclusion, and integrate the largest in closed form, which keeps the
+    // cancellation small when most sellers share a weight.
+    let mut groups: Vec<(f64, usize)> = Vec::new();
+    for w in weights {
+        match groups.iter_mut().find(|(g, _)| *g == w) {
+            Some((_, n)) => *n += 1,
+            None => groups.push((w, 1)),
+        }
+    }
+    groups.sort_by_key(|(_, n)| *n);
+    let (w_l, n_l) = groups.pop().unwrap_or((1., 0));
+
+    let mut terms = vec![(base, 1.)];
+    for (w, n) in groups {
+        let mut new_terms = Vec::with_capacity(terms.len() * (n + 1));
+        for (d, c) in terms {
+            let mut coef = c;
+            for j in 0..=n {
+                new_terms.push((d + j as f64 * w, coef));
+                coef *= -((n - j) as f64) / (j + 1) as f64;
+            }
+        }
+        terms = new_terms;
+    }
+    // ∫_0^1 t^(d - 1) (1 - t^w)^n dt = B(d / w, n + 1) / w = Π_{i=1..n} (i / (d / w + i)) / d.
+    terms
+        .into_iter()
+        .map(|(d, c)| {
+            c / d
+                * (1..=n_l)
+                    .map(|i| i as f64 / (d / w_l + i as f64))
+                    .product::<f64>()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::binom;
+
+    #[test]
+    fn test_weights() {
+        let n = 7;
+        let shapley = Semivalue::Shapley.size_weights(n);
+        let beta_1_1 = "beta:1,1".parse::<Semivalue>().unwrap().size_weights(n);
+        for (a, b) in shapley.iter().zip(&beta_1_1) {
+            assert!((a - b).abs() < 1e-9);
+        }
+        let beta = "beta:16,1".parse::<Semivalue>().unwrap();
+        assert!((beta.size_weights(n).iter().sum::<f64>() - 1.).abs() < 1e-9);
+        assert!(beta.size_weights(n)[0] > beta.size_weights(n)[n - 1]);
+        for (k, w) in Semivalue::Banzhaf.size_weights(n).into_iter().enumerate() {
+            assert!((w - binom(k, n - 1) as f64 / 64.).abs() < 1e-9);
+        }
+
+        // a member of a unanimity game on t sellers is only pivotal to the other t - 1.
+        for value in [Semivalue::Shapley, Semivalue::Banzhaf, beta] {
+            let unanimity = value.unanimity_values(n);
+            for (t, u) in unanimity.iter().enumerate().skip(1) {
+                assert!((u - value.size_weights(t)[t - 1]).abs() < 1e-9);
+            }
+        }
+        assert!("beta:1".parse::<Semivalue>().is_err());
+    }
+
+    #[test]
+    fn test_seller_structure() {
+        let sellers: Vec<SellerId> = (0..5).map(SellerId).collect();
+        // equal weights are the Shapley value.
+        let weights = SellerWeights::default();
+        let size_weights = Semivalue::Shapley.size_weights(5);
+        for k in 0..5 {
+            let p = weights.coalition_weight(
+                sellers[0],
+                sellers[1..=k].iter().copied(),
+                sellers[k + 1..].iter().copied(),
+            );
+            assert!((p - size_weights[k] / binom(k, 4) as f64).abs() < 1e-9);
+        }
+
+        // probabilities of the predecessors of a seller add up to 1.
+        let weights = SellerWeights(
+            [(SellerId(1), 2.), (SellerId(2), 0.5), (SellerId(3), 2.)]
+                .into_iter()
+                .collect(),
+        );
+        let others = &sellers[1..];
+        let total: f64 = (0..1 << others.len())
+            .map(|mask: usize| {
+                let in_subset = |j: &usize| mask >> j & 1 == 1;
+                weights.coalition_weight(
+                    sellers[0],
+                    (0..others.len()).filter(in_subset).map(|j| others[j]),
+                    (0..others.len())
+                        .filter(|j| !in_subset(j))
+                        .map(|j| others[j]),
+                )
+            })
+            .sum();
+        assert!((total - 1.).abs() < 1e-9);
+
+        let unions = SellerUnions::new([
+            ("a".to_string(), vec![SellerId(1), SellerId(2)]),
+            ("b".to_string(), vec![SellerId(0), SellerId(3)]),
+        ])
+        .unwrap();
+        let total: f64 = (0..1 << others.len())
+            .map(|mask: usize| {
+                let in_subset = |j: &usize| mask >> j & 1 == 1;
+                unions.coalition_weight(
+                    sellers[0],
+                    (0..others.len()).filter(in_subset).map(|j| others[j]),
+                    (0..others.len())
+                        .filter(|j| !in_subset(j))
+                        .map(|j| others[j]),
+                )
+            })
+            .sum();
+        assert!((total - 1.).abs() < 1e-9);
+        // {1, 2, 3, 4} is split between the unions a, b and {4}.
+        let v = unions.unanimity_value(SellerId(1), sellers[1..].iter().copied());
+        assert!((v - 1. / 6.).abs() < 1e-9);
+        assert!(SellerUnions::new([
+            ("a".to_string(), vec![SellerId(1)]),
+            ("b".to_string(), vec![SellerId(1)]),
+        ])
+        .is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{SellerId, SellerSet, ShapleyResult};
+use std::collections::HashMap;
+
+/// z-score of a two-sided 95% confidence interval.
+pub const Z_95: f64 = 1.96;
+
+/// Running mean and variance of each seller's sampled contributions, updated with
+/// Welford's algorithm.
+#[derive(Debug, Default, Clone)]
+pub struct SampleStats {
+    count: usize,
+    // seller -> (mean, sum of squared differences from the mean)
+    stats: HashMap<SellerId, (f64, f64)>,
+}
+
+impl SampleStats {
+    pub fn new(sellers: &SellerSet) -> Self {
+        Self {
+            count: 0,
+            stats: sellers.iter().map(|s| (*s, (0., 0.))).collect(),
+        }
+    }
+
+    /// Add one sample. Sellers missing from `sample` contributed 0.
+    pub fn push(&mut self, sample: &HashMap<SellerId, f64>) {
+        self.count += 1;
+        let n = self.count as f64;
+        for (seller, (mean, m2)) in self.stats.iter_mut() {
+            let x = sample.get(seller).copied().unwrap_or_default();
+            let delta = x - *mean;
+            *mean += delta / n;
+            *m2 += delta * (x - *mean);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn means(&self) -> HashMap<SellerId, f64> {
+        self.stats
+            .iter()
+            .map(|(s, (mean, _))| (*s, *mean))
+            .collect()
+    }
+
+    /// Unbiased sample variance of each seller's contributions.
+    pub fn variances(&self) -> HashMap<SellerId, f64> {
+        self.stats
+            .iter()
+            .map(|(s, (_, m2))| (*s, self.variance(*m2)))
+            .collect()
+    }
+
+    fn variance(&self, m2: f64) -> f64 {
+        if self.count < 2 {
+            f64::INFINITY
+        } else {
+            m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// Largest standard error of the running means.
+    pub fn max_std_error(&self) -> f64 {
+        self.stats
+            .values()
+            .map(|(_, m2)| (self.variance(*m2) / self.count as f64).sqrt())
+            .fold(0., f64::max)
+    }
+
+    /// Normal-approximation confidence interval of each seller's mean.
+    pub fn confidence_intervals(&self, z: f64) -> HashMap<SellerId, (f64, f64)> {
+        self.stats
+            .iter()
+            .map(|(s, (mean, m2))| {
+                let half_width = z * (self.variance(*m2) / self.count as f64).sqrt();
+                (*s, (mean - half_width, mean + half_width))
+            })
+            .collect()
+    }
+
+    /// Fill in the estimated values along with their sampling statistics.
+    pub fn fill_result(&self, result: &mut ShapleyResult) {
+        result.shapley_values = self.means();
+        result.num_samples = self.count;
+        result.variances = self.variances();
+        result.confidence_intervals = self.confidence_intervals(Z_95);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let sellers: SellerSet = vec![0, 1].into_iter().collect();
+        let mut stats = SampleStats::new(&sellers);
+        for x in [1., 2., 3., 4.] {
+            stats.push(&HashMap::from([(SellerId(0), x)]));
+        }
+        assert_eq!(stats.count(), 4);
+        assert!((stats.means()[&SellerId(0)] - 2.5).abs() < 1e-9);
+        assert!((stats.variances()[&SellerId(0)] - 5. / 3.).abs() < 1e-9);
+        assert_eq!(stats.means()[&SellerId(1)], 0.);
+        assert_eq!(stats.variances()[&SellerId(1)], 0.);
+        let (lo, hi) = stats.confidence_intervals(Z_95)[&SellerId(0)];
+        assert!(lo < 2.5 && 2.5 < hi);
+    }
+}
@@ -1,11 +1,19 @@
-use crate::{alg::join::join, DataSet, SellerSet, PLANS, ROW_ID_COL_NAME};
+use crate::{
+    alg::join::{answer, join},
+    DataSet, JoinPlan, SellerSet, Utility, ROW_ID_COL_NAME,
+};
 use anyhow::{Context, Result};
 use dashmap::DashMap;
 use polars::prelude::*;
 use rayon::prelude::*;
 use std::collections::HashMap;
 
-pub fn subset_utility(dataset: &DataSet, subset: &SellerSet) -> Result<f64> {
+pub fn subset_utility(dataset: &DataSet, utility: &dyn Utility, subset: &SellerSet) -> Result<f64> {
+    let plan = dataset.plan();
+    if plan.has_outer_join() {
+        return subset_utility_outer_join(dataset, plan, utility, subset);
+    }
+
     let tables: HashMap<&str, DataFrame> = dataset
         .tables
         .par_iter()
@@ -17,11 +25,10 @@ pub fn subset_utility(dataset: &DataSet, subset: &SellerSet) -> Result<f64> {
                 .into_iter()
                 .map(|row_id| {
                     let row_id = row_id.context("cannot find row_id")?.into();
-                    let seller = table
-                        .seller_map
-                        .get(&row_id)
-                        .context("cannot get seller set")?;
-                    Ok(seller.intersection(subset).next().is_some())
+                    let owners = table.owners(row_id).with_context(|| {
+                        format!("row id {} of {} has no owner", row_id, table_name)
+                    })?;
+                    Ok(owners.available(subset))
                 })
                 .collect::<Result<BooleanChunked>>()?;
             let mut df = table.df.filter(&mask)?;
@@ -30,18 +37,69 @@ pub fn subset_utility(dataset: &DataSet, subset: &SellerSet) -> Result<f64> {
         })
         .collect::<Result<HashMap<_, _>>>()?;
 
+    let df = join(|table_name| tables.get(table_name), plan)?;
+    utility.evaluate(&answer(&df, plan)?)
+}
+
+/// Joining the tables restricted to a subset under an outer join would produce
+/// null-extended tuples that do not exist in the full join, and the utility would no longer
+/// be monotone. Instead, count the tuples of the full join whose rows are all available to
+/// the subset; null-extended tables impose no condition.
+fn subset_utility_outer_join(
+    dataset: &DataSet,
+    plan: &JoinPlan,
+    utility: &dyn Utility,
+    subset: &SellerSet,
+) -> Result<f64> {
     let df = join(
-        |table_name| tables.get(table_name),
-        PLANS
-            .get(dataset.name.as_str())
-            .context("cannot find join plan")?,
+        |table_name| dataset.tables.get(table_name).map(|t| &t.df),
+        plan,
     )?;
-    Ok(df.shape().0 as f64)
+
+    let mut mask = BooleanChunked::full("mask", true, df.height());
+    for table_name in plan.tables() {
+        let table = dataset
+            .tables
+            .get(table_name)
+            .context("cannot find table")?;
+        let available = df
+            .column(&format!("{}:{}", ROW_ID_COL_NAME, table_name))?
+            .u64()?
+            .into_iter()
+            .map(|row_id| match row_id {
+                Some(row_id) => {
+                    let owners = table.owners(row_id.into()).with_context(|| {
+                        format!("row id {} of {} has no owner", row_id, table_name)
+                    })?;
+                    Ok(owners.available(subset))
+                }
+                None => Ok(true),
+            })
+            .collect::<Result<BooleanChunked>>()?;
+        mask = &mask & &available;
+    }
+    let df = df.filter(&mask)?;
+    if plan.projection.is_some() {
+        return utility.evaluate(&answer(&df, plan)?);
+    }
+
+    // tuples differing only in their semi join witnesses are the same tuple.
+    let semi_tables: Vec<&str> = plan.semi_tables().collect();
+    if semi_tables.is_empty() {
+        return utility.evaluate(&df);
+    }
+    let key_columns: Vec<String> = plan
+        .tables()
+        .filter(|t| !semi_tables.contains(t))
+        .map(|t| format!("{}:{}", ROW_ID_COL_NAME, t))
+        .collect();
+    utility.evaluate(&df.drop_duplicates(false, Some(&key_columns))?)
 }
 
 #[inline]
 pub fn subset_utility_with_cache(
     dataset: &DataSet,
+    utility: &dyn Utility,
     subset: SellerSet,
     cache: &DashMap<SellerSet, f64>,
 ) -> Result<f64> {
@@ -49,7 +107,7 @@ pub fn subset_utility_with_cache(
         return Ok(*u);
     }
 
-    let u = subset_utility(dataset, &subset)?;
+    let u = subset_utility(dataset, utility, &subset)?;
     cache.insert(subset, u);
     Ok(u)
 }
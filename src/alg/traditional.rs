@@ -1,5 +1,6 @@
 use crate::{
-    alg::subset_utility::subset_utility_with_cache, DataSet, SellerId, SellerSet, ShapleyResult,
+    alg::subset_utility::subset_utility_with_cache, utils::binom, DataSet, SellerId, SellerSet,
+    SellerStructure, Semivalue, ShapleyResult, Utility,
 };
 use anyhow::Result;
 use dashmap::DashMap;
@@ -7,12 +8,19 @@ use itertools::Itertools;
 use rayon::prelude::*;
 use std::{collections::HashMap, time::Instant};
 
-pub fn traditional_scheme(dataset: &DataSet) -> Result<ShapleyResult> {
-    info!("traditional scheme...");
+pub fn traditional_scheme(
+    dataset: &DataSet,
+    utility: &dyn Utility,
+    value: &Semivalue,
+) -> Result<ShapleyResult> {
+    info!("traditional scheme ({})...", value);
     let begin = Instant::now();
     let cache: DashMap<SellerSet, f64> = DashMap::new();
     let cache_ref = &cache;
     let seller_len = dataset.sellers.len();
+    let size_weights = value.size_weights(seller_len);
+    let size_weights_ref = &size_weights;
+    let structure = SellerStructure::of(dataset, value)?;
     let shapley_values = dataset
         .sellers
         .par_iter()
@@ -22,7 +30,9 @@ pub fn traditional_scheme(dataset: &DataSet) -> Result<ShapleyResult> {
             let contribution = (0..seller_len)
                 .into_par_iter()
                 .map(move |k| {
-                    let (utility, count) = dataset
+                    // every k-coalition weighs the same, unless sellers are structured.
+                    let coalition_weight = size_weights_ref[k] / binom(k, seller_len - 1) as f64;
+                    dataset
                         .sellers
                         .iter()
                         .copied()
@@ -31,22 +41,37 @@ pub fn traditional_scheme(dataset: &DataSet) -> Result<ShapleyResult> {
                         .par_bridge()
                         .map(|subset| {
                             let mut subset = SellerSet(subset.into_iter().collect());
-                            let utility_without_seller =
-                                subset_utility_with_cache(dataset, subset.clone(), cache_ref)?;
+                            let coalition_weight = match structure {
+                                Some(structure) => structure.coalition_weight(
+                                    seller,
+                                    subset.iter().copied(),
+                                    dataset
+                                        .sellers
+                                        .iter()
+                                        .copied()
+                                        .filter(|s| *s != seller && !subset.contains(s)),
+                                ),
+                                None => coalition_weight,
+                            };
+                            let utility_without_seller = subset_utility_with_cache(
+                                dataset,
+                                utility,
+                                subset.clone(),
+                                cache_ref,
+                            )?;
                             subset.insert(seller);
                             let utility_with_seller =
-                                subset_utility_with_cache(dataset, subset, cache_ref)?;
-                            Ok((utility_with_seller - utility_without_seller, 1.))
+                                subset_utility_with_cache(dataset, utility, subset, cache_ref)?;
+                            Ok(coalition_weight * (utility_with_seller - utility_without_seller))
                         })
                         .reduce(
-                            || Ok((0., 0.)),
+                            || Ok(0.),
                             |a: Result<_>, b: Result<_>| {
                                 let a = a?;
                                 let b = b?;
-                                Ok((a.0 + b.0, a.1 + b.1))
+                                Ok(a + b)
                             },
-                        )?;
-                    Ok(utility / count)
+                        )
                 })
                 .reduce(
                     || Ok(0.),
@@ -58,14 +83,19 @@ pub fn traditional_scheme(dataset: &DataSet) -> Result<ShapleyResult> {
                 )?;
 
             info!("seller #{} done", seller);
-            Ok((seller, contribution / seller_len as f64))
+            Ok((seller, contribution))
         })
         .collect::<Result<HashMap<SellerId, f64>>>()?;
     let total_time = Instant::now() - begin;
     let avg_time = total_time / seller_len as u32;
     info!("done in {:?}", total_time);
+    let union_values = match structure {
+        Some(SellerStructure::Unions(unions)) => unions.union_values(&shapley_values),
+        _ => HashMap::new(),
+    };
     Ok(ShapleyResult {
         shapley_values,
+        union_values,
         avg_time,
         total_time,
         ..Default::default()
@@ -75,19 +105,16 @@ pub fn traditional_scheme(dataset: &DataSet) -> Result<ShapleyResult> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::{assert_world_sv, test_data_dir};
+    use crate::{
+        utils::{assert_world_sv, load_world},
+        TupleCount,
+    };
 
     #[test]
     fn test() {
         polars_core::POOL.install(|| {
-            let data_dir = test_data_dir();
-            let world = DataSet::load(
-                "world",
-                data_dir.join("world"),
-                data_dir.join("world-metadata"),
-            )
-            .unwrap();
-            let r = traditional_scheme(&world).unwrap();
+            let world = load_world();
+            let r = traditional_scheme(&world, &TupleCount, &Semivalue::Shapley).unwrap();
             dbg!(&r);
             let actual = r.shapley_values.values().sum::<f64>();
             assert!((actual - 30670.).abs() < 1e-5);
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use shapley_value::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Convert the -index.json and -seller.json files of a dataset to a consolidated ownership
+/// file of (table, row_id, seller) triples
+#[derive(Debug, StructOpt)]
+struct Opts {
+    /// Input csv directory
+    #[structopt(short = "i", long, parse(from_os_str))]
+    csv_dir: PathBuf,
+
+    /// Input meta directory
+    #[structopt(short, long, parse(from_os_str))]
+    meta_dir: PathBuf,
+
+    /// Output file: csv if it ends in .csv, otherwise parquet. Defaults to
+    /// <meta dir>/ownership.parquet
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    utils::init_tracing_subscriber("info")?;
+    let opts = Opts::from_args();
+    let output = opts
+        .output
+        .unwrap_or_else(|| opts.meta_dir.join("ownership.parquet"));
+    convert_ownership(&opts.csv_dir, &opts.meta_dir, output)
+}
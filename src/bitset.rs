@@ -0,0 +1,283 @@
+use crate::{SellerId, SellerSet};
+use std::{collections::HashMap, hash::Hash};
+
+/// Dense positions of a set of sellers, so that sets of them can be stored as bits.
+#[derive(Debug, Default, Clone)]
+pub struct SellerIndex {
+    ids: Vec<SellerId>,
+    positions: HashMap<SellerId, usize>,
+}
+
+impl SellerIndex {
+    pub fn new(sellers: impl IntoIterator<Item = SellerId>) -> Self {
+        let mut ids: Vec<SellerId> = sellers.into_iter().collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let positions = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        Self { ids, positions }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    #[inline]
+    pub fn position(&self, seller: SellerId) -> Option<usize> {
+        self.positions.get(&seller).copied()
+    }
+
+    #[inline]
+    pub fn id(&self, position: usize) -> SellerId {
+        self.ids[position]
+    }
+
+    /// Bits of the sellers of `set`, which must all be indexed.
+    pub fn encode<B: SellerBits>(&self, set: &SellerSet) -> B {
+        let mut bits = B::empty(self.len());
+        for seller in set.iter() {
+            bits.insert(self.positions[seller]);
+        }
+        bits
+    }
+
+    pub fn decode<B: SellerBits>(&self, bits: &B) -> SellerSet {
+        bits.ones().map(|i| self.ids[i]).collect()
+    }
+}
+
+/// Set of sellers as bits of their positions in a [`SellerIndex`]: `u64` and `u128` for up
+/// to 64 and 128 sellers, and [`WideBits`] beyond.
+pub trait SellerBits: Clone + Eq + Hash + Send + Sync {
+    /// Empty set over `width` sellers.
+    fn empty(width: usize) -> Self;
+
+    fn num_words(&self) -> usize;
+
+    /// The `i`-th 64 bits.
+    fn word(&self, i: usize) -> u64;
+
+    fn insert(&mut self, position: usize);
+
+    fn contains(&self, position: usize) -> bool;
+
+    fn union_with(&mut self, other: &Self);
+
+    fn is_subset(&self, other: &Self) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Positions of the sellers in the set, in increasing order.
+    fn ones(&self) -> Ones<'_, Self> {
+        Ones {
+            bits: self,
+            word: 0,
+            current: if self.num_words() == 0 {
+                0
+            } else {
+                self.word(0)
+            },
+        }
+    }
+}
+
+impl SellerBits for u64 {
+    #[inline]
+    fn empty(width: usize) -> Self {
+        debug_assert!(width <= 64);
+        0
+    }
+
+    #[inline]
+    fn num_words(&self) -> usize {
+        1
+    }
+
+    #[inline]
+    fn word(&self, _: usize) -> u64 {
+        *self
+    }
+
+    #[inline]
+    fn insert(&mut self, position: usize) {
+        *self |= 1 << position;
+    }
+
+    #[inline]
+    fn contains(&self, position: usize) -> bool {
+        *self >> position & 1 == 1
+    }
+
+    #[inline]
+    fn union_with(&mut self, other: &Self) {
+        *self |= other;
+    }
+
+    #[inline]
+    fn is_subset(&self, other: &Self) -> bool {
+        self & !other == 0
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.count_ones() as usize
+    }
+}
+
+impl SellerBits for u128 {
+    #[inline]
+    fn empty(width: usize) -> Self {
+        debug_assert!(width <= 128);
+        0
+    }
+
+    #[inline]
+    fn num_words(&self) -> usize {
+        2
+    }
+
+    #[inline]
+    fn word(&self, i: usize) -> u64 {
+        (self >> (64 * i)) as u64
+    }
+
+    #[inline]
+    fn insert(&mut self, position: usize) {
+        *self |= 1 << position;
+    }
+
+    #[inline]
+    fn contains(&self, position: usize) -> bool {
+        *self >> position & 1 == 1
+    }
+
+    #[inline]
+    fn union_with(&mut self, other: &Self) {
+        *self |= other;
+    }
+
+    #[inline]
+    fn is_subset(&self, other: &Self) -> bool {
+        self & !other == 0
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.count_ones() as usize
+    }
+}
+
+/// Bits of any number of sellers. Sets to compare or merge must have the same width.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WideBits(Vec<u64>);
+
+impl SellerBits for WideBits {
+    fn empty(width: usize) -> Self {
+        Self(vec![0; width.div_ceil(64)])
+    }
+
+    #[inline]
+    fn num_words(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn word(&self, i: usize) -> u64 {
+        self.0[i]
+    }
+
+    #[inline]
+    fn insert(&mut self, position: usize) {
+        self.0[position / 64] |= 1 << (position % 64);
+    }
+
+    #[inline]
+    fn contains(&self, position: usize) -> bool {
+        self.0[position / 64] >> (position % 64) & 1 == 1
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+
+    fn is_subset(&self, other: &Self) -> bool {
+        self.0.iter().zip(&other.0).all(|(a, b)| a & !b == 0)
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+pub struct Ones<'a, B: ?Sized> {
+    bits: &'a B,
+    word: usize,
+    current: u64,
+}
+
+impl<'a, B: SellerBits> Iterator for Ones<'a, B> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.current == 0 {
+            self.word += 1;
+            if self.word >= self.bits.num_words() {
+                return None;
+            }
+            self.current = self.bits.word(self.word);
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(self.word * 64 + bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<B: SellerBits>(index: &SellerIndex) {
+        let a: SellerSet = vec![3, 70, 200].into_iter().collect();
+        let b: SellerSet = vec![3, 5, 70, 200].into_iter().collect();
+        let a_bits: B = index.encode(&a);
+        let b_bits: B = index.encode(&b);
+        assert_eq!(index.decode(&a_bits), a);
+        assert_eq!(a_bits.len(), 3);
+        assert!(a_bits.is_subset(&b_bits));
+        assert!(!b_bits.is_subset(&a_bits));
+        assert!(a_bits.contains(index.position(SellerId(70)).unwrap()));
+        assert!(!a_bits.contains(index.position(SellerId(5)).unwrap()));
+
+        let mut c_bits: B = index.encode(&vec![1].into_iter().collect());
+        c_bits.union_with(&a_bits);
+        assert_eq!(
+            index.decode(&c_bits),
+            vec![1, 3, 70, 200].into_iter().collect::<SellerSet>()
+        );
+    }
+
+    #[test]
+    fn test_bits() {
+        // positions are dense whatever the seller ids.
+        let index = SellerIndex::new([1, 3, 5, 70, 200].map(SellerId));
+        check::<u64>(&index);
+        check::<u128>(&index);
+        check::<WideBits>(&index);
+
+        let index = SellerIndex::new((0..300).map(SellerId));
+        check::<WideBits>(&index);
+        let all: WideBits = index.encode(&(0..300).collect());
+        assert_eq!(all.ones().collect::<Vec<_>>(), (0..300).collect::<Vec<_>>());
+    }
+}
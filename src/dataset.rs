@@ -1,8 +1,11 @@
-use crate::{SellerSet, Table};
-use anyhow::Result;
-use glob::glob;
+use crate::{
+    ownership::take_owners, ownership_path, read_ownership, read_table, JoinPlan, Manifest, Owners,
+    SellerInfo, SellerRegistry, SellerSet, SellerUnions, SellerWeights, Table,
+};
+use anyhow::{Context, Result};
+use polars::prelude::DataFrame;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap},
     path::Path,
     time::Instant,
 };
@@ -12,53 +15,205 @@ pub struct DataSet {
     pub name: String,
     pub tables: HashMap<String, Table>,
     pub sellers: SellerSet,
+    /// External names of the sellers, from `sellers.json` in the meta dir if any, otherwise
+    /// from the names in the `-seller.json` files.
+    pub registry: SellerRegistry,
+    /// Weights from `seller-weights.json` in the meta dir, if any. Makes the Shapley value
+    /// weighted.
+    pub seller_weights: Option<SellerWeights>,
+    /// Unions from `seller-unions.json` in the meta dir, if any. Makes the Shapley value an
+    /// Owen value.
+    pub seller_unions: Option<SellerUnions>,
+    /// Join plan of the dataset, checked against its tables.
+    pub plan: JoinPlan,
 }
 
 impl DataSet {
+    /// Load the tables of `csv_dir` with their metadata from `meta_dir`, checking `plan`
+    /// against them.
     pub fn load(
         name: impl Into<String>,
         csv_dir: impl AsRef<Path>,
         meta_dir: impl AsRef<Path>,
+        plan: JoinPlan,
     ) -> Result<Self> {
         let begin = Instant::now();
         let csv_dir = csv_dir.as_ref();
         let meta_dir = meta_dir.as_ref();
-        info!("load csv from {}...", csv_dir.display());
+        info!("load tables from {}...", csv_dir.display());
         info!("load meta from {}...", meta_dir.display());
 
+        let mut ownership = match ownership_path(meta_dir) {
+            Some(path) => {
+                info!("load ownership from {}...", path.display());
+                Some(read_ownership(path)?)
+            }
+            None => None,
+        };
+        let mut data = vec![];
+        for (name, path, options) in Manifest::load(csv_dir)?.table_files(csv_dir)? {
+            let owners = ownership
+                .as_mut()
+                .map(|ownership| ownership.remove(&name).unwrap_or_default());
+            let (df, owners) = read_table(&name, &path, &options, meta_dir, owners)?;
+            data.push((name, df, owners));
+        }
+        let sellers_f = meta_dir.join("sellers.json");
+        let registry = if sellers_f.exists() {
+            info!("load sellers from {}...", sellers_f.display());
+            SellerRegistry::load(sellers_f)?
+        } else {
+            SellerRegistry::from_names(data.iter().flat_map(|(_, _, owners)| owners.names()))
+        };
+        let sellers = registry.sellers();
+
         let mut tables = HashMap::new();
-        for csv_f in glob(&csv_dir.join("*.csv").to_string_lossy())? {
-            let csv_f = csv_f?;
-            let name = csv_f.file_stem().unwrap().to_string_lossy().to_string();
-            let row_id_f = meta_dir.join(format!("{name}-index.json"));
-            let seller_f = meta_dir.join(format!("{name}-seller.json"));
-            let table = Table::load(name.clone(), csv_f, row_id_f, seller_f)?;
+        for (name, df, owners) in data {
+            let table = Table::new(name.clone(), df, owners, &registry)?;
             tables.insert(name, table);
         }
-        // TODO: store seller list directly
-        let sellers = {
-            let mut sellers = HashSet::new();
-            for t in tables.values() {
-                for s in t.seller_map.values() {
-                    sellers.extend(s.iter().copied());
-                }
-            }
-            SellerSet::new(sellers.into_iter().collect())
+        let seller_weights_f = meta_dir.join("seller-weights.json");
+        let seller_weights = if seller_weights_f.exists() {
+            info!("load seller weights from {}...", seller_weights_f.display());
+            Some(SellerWeights::load(seller_weights_f, &registry)?)
+        } else {
+            None
+        };
+        let seller_unions_f = meta_dir.join("seller-unions.json");
+        let seller_unions = if seller_unions_f.exists() {
+            info!("load seller unions from {}...", seller_unions_f.display());
+            Some(SellerUnions::load(seller_unions_f, &registry)?)
+        } else {
+            None
         };
 
-        info!("done in {:?}", Instant::now() - begin);
-        Ok(Self {
+        let dataset = Self {
             name: name.into(),
             tables,
             sellers,
-        })
+            registry,
+            seller_weights,
+            seller_unions,
+            plan,
+        };
+        dataset.plan.validate(&dataset)?;
+        info!("done in {:?}", Instant::now() - begin);
+        Ok(dataset)
+    }
+
+    pub fn with_plan(mut self, plan: JoinPlan) -> Self {
+        self.plan = plan;
+        self
+    }
+
+    #[inline]
+    pub fn plan(&self) -> &JoinPlan {
+        &self.plan
+    }
+}
+
+/// Builds a [`DataSet`] from tables already in memory, with its own join plan.
+#[derive(Debug, Default)]
+pub struct DataSetBuilder {
+    name: String,
+    tables: Vec<(String, DataFrame, Owners)>,
+    sellers: Option<Vec<SellerInfo>>,
+    plan: Option<JoinPlan>,
+    seller_weights: Option<HashMap<String, f64>>,
+    seller_unions: Option<BTreeMap<String, Vec<String>>>,
+}
+
+impl DataSetBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Add table `name`, whose rows are owned by `owners`. Rows are identified by the
+    /// `_row_id` column of `df` if it has one, and by their positions otherwise.
+    pub fn table(mut self, name: impl Into<String>, df: DataFrame, owners: Owners) -> Self {
+        self.tables.push((name.into(), df, owners));
+        self
+    }
+
+    /// Declare the sellers. By default they are the owners of rows of the tables.
+    pub fn sellers(mut self, sellers: impl IntoIterator<Item = SellerInfo>) -> Self {
+        self.sellers = Some(sellers.into_iter().collect());
+        self
+    }
+
+    pub fn plan(mut self, plan: JoinPlan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+
+    /// Weights of sellers by name. Makes the Shapley value weighted.
+    pub fn seller_weights(mut self, weights: HashMap<String, f64>) -> Self {
+        self.seller_weights = Some(weights);
+        self
+    }
+
+    /// Unions of sellers by name. Makes the Shapley value an Owen value.
+    pub fn seller_unions(mut self, unions: BTreeMap<String, Vec<String>>) -> Self {
+        self.seller_unions = Some(unions);
+        self
+    }
+
+    /// Build the dataset, checking its join plan against its tables.
+    pub fn build(self) -> Result<DataSet> {
+        let plan = self
+            .plan
+            .with_context(|| format!("no join plan for {}", self.name))?;
+        let mut data = vec![];
+        for (name, mut df, owners) in self.tables {
+            let owners = take_owners(&name, &mut df, Some(owners))?.unwrap_or_default();
+            data.push((name, df, owners));
+        }
+        let registry = match self.sellers {
+            Some(sellers) => SellerRegistry::new(sellers)?,
+            None => {
+                SellerRegistry::from_names(data.iter().flat_map(|(_, _, owners)| owners.names()))
+            }
+        };
+
+        let mut tables = HashMap::new();
+        for (name, df, owners) in data {
+            let table = Table::new(name.clone(), df, owners, &registry)?;
+            tables.insert(name, table);
+        }
+        let seller_weights = self
+            .seller_weights
+            .map(|weights| SellerWeights::from_names(weights, &registry))
+            .transpose()?;
+        let seller_unions = self
+            .seller_unions
+            .map(|unions| SellerUnions::from_names(unions, &registry))
+            .transpose()?;
+
+        let dataset = DataSet {
+            name: self.name,
+            tables,
+            sellers: registry.sellers(),
+            registry,
+            seller_weights,
+            seller_unions,
+            plan,
+        };
+        dataset.plan().validate(&dataset)?;
+        Ok(dataset)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::test_data_dir;
+    use crate::{
+        get_plan,
+        utils::{load_world, test_data_dir, TempDir},
+        JoinStep, ROW_ID_COL_NAME,
+    };
 
     #[test]
     fn test_load() {
@@ -67,8 +222,180 @@ mod tests {
             "world",
             data_dir.join("world"),
             data_dir.join("world-metadata"),
+            get_plan("world").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(world.tables.len(), 3);
+        assert_eq!(world.tables["city"].df.height(), 4079);
+        assert_eq!(world.sellers, world.registry.sellers());
+        // every row of every table has an owner.
+        for table in world.tables.values() {
+            assert_eq!(table.seller_map.len(), table.df.height());
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        use polars::prelude::*;
+
+        let data_dir = test_data_dir();
+        let world = load_world();
+        let tmp_dir = TempDir::new("formats");
+        let df = |table: &str| world.tables[table].df.drop(ROW_ID_COL_NAME).unwrap();
+        let create = |f: &str| std::fs::File::create(tmp_dir.path().join(f)).unwrap();
+        ParquetWriter::new(create("city.parquet"))
+            .finish(&df("city"))
+            .unwrap();
+        IpcWriter::new(create("country.arrow"))
+            .finish(&df("country"))
+            .unwrap();
+        CsvWriter::new(create("languages.txt"))
+            .with_delimiter(b';')
+            .finish(&df("countrylanguage"))
+            .unwrap();
+        std::fs::write(
+            tmp_dir.path().join("manifest.json"),
+            r#"{"tables": {
+                "city": {"dtypes": {"Population": "f64"}},
+                "countrylanguage": {"path": "languages.txt", "format": "csv", "delimiter": ";",
+                                    "dtypes": {"Percentage": "str"}}
+            }}"#,
+        )
+        .unwrap();
+
+        let loaded = DataSet::load(
+            "world",
+            tmp_dir.path(),
+            data_dir.join("world-metadata"),
+            get_plan("world").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(loaded.sellers, world.sellers);
+        for (name, table) in &world.tables {
+            let df = &loaded.tables[name].df;
+            assert_eq!(df.shape(), table.df.shape());
+            assert_eq!(loaded.tables[name].seller_map, table.seller_map);
+        }
+        let dtype = |table: &str, column: &str| {
+            loaded.tables[table]
+                .df
+                .column(column)
+                .unwrap()
+                .dtype()
+                .clone()
+        };
+        assert_eq!(dtype("city", "Population"), DataType::Float64);
+        assert_eq!(dtype("country", "Code"), DataType::Utf8);
+        assert_eq!(dtype("countrylanguage", "Percentage"), DataType::Utf8);
+    }
+
+    #[test]
+    fn test_registry() {
+        let data_dir = test_data_dir();
+        let tmp_dir = TempDir::new("registry");
+        let meta_dir = tmp_dir.path();
+        // name seller n "s<n>", and list them in reverse.
+        for table in ["city", "country", "countrylanguage"] {
+            let index_f = format!("{}-index.json", table);
+            std::fs::copy(
+                data_dir.join("world-metadata").join(&index_f),
+                meta_dir.join(&index_f),
+            )
+            .unwrap();
+            let seller_f = format!("{}-seller.json", table);
+            let mut seller: serde_json::Value = serde_json::from_slice(
+                &std::fs::read(data_dir.join("world-metadata").join(&seller_f)).unwrap(),
+            )
+            .unwrap();
+            for v in seller["seller"].as_object_mut().unwrap().values_mut() {
+                *v = format!("s{}", v).into();
+            }
+            std::fs::write(meta_dir.join(&seller_f), seller.to_string()).unwrap();
+        }
+        std::fs::write(
+            meta_dir.join("sellers.json"),
+            r#"["s5", "s4", "s3", "s2", "s1", {"name": "s0", "display_name": "Zero"}]"#,
+        )
+        .unwrap();
+
+        let world = DataSet::load(
+            "world",
+            data_dir.join("world"),
+            meta_dir,
+            get_plan("world").unwrap(),
         )
         .unwrap();
-        dbg!(&world.sellers);
+        assert_eq!(world.registry.len(), 6);
+        assert_eq!(world.registry.id("s5"), Some(crate::SellerId(0)));
+        let s0 = world.registry.resolve("s0").unwrap();
+        assert_eq!(
+            world.registry.info(s0).display_name.as_deref(),
+            Some("Zero")
+        );
+        assert_eq!(world.sellers, world.registry.sellers());
+    }
+
+    #[test]
+    fn test_builder() {
+        polars_core::POOL.install(|| {
+            let world = load_world();
+            // a name no plan is registered under.
+            let mut builder = DataSetBuilder::new("world-in-memory");
+            let registry = &world.registry;
+            for (name, table) in &world.tables {
+                let owners = table
+                    .seller_map
+                    .iter()
+                    .flat_map(|(row_id, sellers)| {
+                        sellers.iter().map(move |s| (*row_id, registry.name(*s)))
+                    })
+                    .collect();
+                builder = builder.table(name.clone(), table.df.clone(), owners);
+            }
+            assert!(DataSetBuilder::new("world-in-memory").build().is_err());
+            let dataset = builder.plan(get_plan("world").unwrap()).build().unwrap();
+            assert_eq!(dataset.sellers, world.sellers);
+            let r = crate::alg::proposed::proposed_scheme(
+                &dataset,
+                &crate::TupleCount,
+                &Default::default(),
+            )
+            .unwrap();
+            crate::utils::assert_world_sv(&r.shapley_values);
+        });
+    }
+
+    #[test]
+    fn test_plan() {
+        polars_core::POOL.install(|| {
+            let data_dir = test_data_dir();
+            // a name no plan is registered under.
+            let sample = DataSet::load(
+                "world-sample-3",
+                data_dir.join("world"),
+                data_dir.join("world-metadata"),
+                get_plan("world").unwrap(),
+            )
+            .unwrap();
+            // a plan joining a table the dataset does not have.
+            let plan = JoinPlan::new(
+                "city",
+                vec![JoinStep::new("planet", &["CountryCode"], &["Code"])],
+            );
+            assert!(DataSet::load(
+                "world",
+                data_dir.join("world"),
+                data_dir.join("world-metadata"),
+                plan,
+            )
+            .is_err());
+            let r = crate::alg::proposed::proposed_scheme(
+                &sample,
+                &crate::TupleCount,
+                &Default::default(),
+            )
+            .unwrap();
+            crate::utils::assert_world_sv(&r.shapley_values);
+        });
     }
 }
@@ -0,0 +1,469 @@
+//! A small expression language over the columns of a joined result.
+//!
+//! ```text
+//! expr    := or
+//! or      := and ("||" and)*
+//! and     := not ("&&" not)*
+//! not     := "!" not | cmp
+//! cmp     := add (("==" | "!=" | "<" | "<=" | ">" | ">=") add)?
+//! add     := mul (("+" | "-") mul)*
+//! mul     := unary (("*" | "/") unary)*
+//! unary   := "-" unary | primary
+//! primary := number | string | "true" | "false" | "null" | column | "(" expr ")"
+//! ```
+//!
+//! Columns are bare identifiers (which may contain `:`, e.g. `Name:country`) or quoted in
+//! backticks. Strings are quoted in `'` or `"`. Null propagates through arithmetic and
+//! comparison, and counts as false in logic.
+
+use anyhow::{bail, ensure, Context, Error, Result};
+use polars::prelude::*;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::BTreeSet,
+    fmt,
+    iter::Peekable,
+    str::{Chars, FromStr},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(String),
+    Literal(Value),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Columns referenced by the expression.
+    pub fn columns(&self) -> BTreeSet<&str> {
+        let mut ans = BTreeSet::new();
+        self.collect_columns(&mut ans);
+        ans
+    }
+
+    fn collect_columns<'a>(&'a self, ans: &mut BTreeSet<&'a str>) {
+        match self {
+            Expr::Column(c) => {
+                ans.insert(c.as_str());
+            }
+            Expr::Literal(_) => {}
+            Expr::Unary(_, e) => e.collect_columns(ans),
+            Expr::Binary(_, l, r) => {
+                l.collect_columns(ans);
+                r.collect_columns(ans);
+            }
+        }
+    }
+
+    /// Evaluate a numeric expression. Null counts as 0.
+    pub fn eval_f64(&self, df: &DataFrame) -> Result<Vec<f64>> {
+        match self.eval(df)? {
+            Column::Null => Ok(vec![0.; df.height()]),
+            Column::Num(v) => Ok(v.into_iter().map(|v| v.unwrap_or(0.)).collect()),
+            v => bail!("expect a number from `{}`, got a {}", self, v.kind()),
+        }
+    }
+
+    /// Evaluate a predicate. Null counts as false.
+    pub fn eval_mask(&self, df: &DataFrame) -> Result<BooleanChunked> {
+        self.eval(df)?
+            .truthy(df.height())
+            .with_context(|| format!("expect a boolean from `{}`", self))
+    }
+
+    /// Evaluate the expression on all rows of `df` at once.
+    fn eval(&self, df: &DataFrame) -> Result<Column> {
+        let height = df.height();
+        Ok(match self {
+            Expr::Column(c) => {
+                let s = df.column(c)?;
+                match s.dtype() {
+                    DataType::Boolean => Column::Bool(s.bool()?.clone()),
+                    DataType::Utf8 => Column::Str(s.utf8()?.clone()),
+                    _ if s.is_numeric() => Column::Num(s.cast(&DataType::Float64)?.f64()?.clone()),
+                    dtype => bail!("unsupported type {:?} of column {} in expression", dtype, c),
+                }
+            }
+            Expr::Literal(Value::Null) => Column::Null,
+            Expr::Literal(Value::Bool(v)) => Column::Bool(BooleanChunked::full("", *v, height)),
+            Expr::Literal(Value::Num(v)) => Column::Num(Float64Chunked::full("", *v, height)),
+            Expr::Literal(Value::Str(v)) => Column::Str(Utf8Chunked::full("", v, height)),
+            Expr::Unary(op, e) => match (op, e.eval(df)?) {
+                (_, Column::Null) => Column::Null,
+                (UnaryOp::Neg, Column::Num(v)) => Column::Num(v.apply(|v| -v)),
+                (UnaryOp::Not, Column::Bool(v)) => Column::Bool(!&v),
+                (op, v) => bail!("cannot apply {:?} to a {}", op, v.kind()),
+            },
+            Expr::Binary(BinaryOp::And, l, r) => {
+                Column::Bool(&l.eval(df)?.truthy(height)? & &r.eval(df)?.truthy(height)?)
+            }
+            Expr::Binary(BinaryOp::Or, l, r) => {
+                Column::Bool(&l.eval(df)?.truthy(height)? | &r.eval(df)?.truthy(height)?)
+            }
+            Expr::Binary(op, l, r) => match (*op, l.eval(df)?, r.eval(df)?) {
+                (_, Column::Null, _) | (_, _, Column::Null) => Column::Null,
+                (BinaryOp::Add, Column::Num(l), Column::Num(r)) => Column::Num(&l + &r),
+                (BinaryOp::Sub, Column::Num(l), Column::Num(r)) => Column::Num(&l - &r),
+                (BinaryOp::Mul, Column::Num(l), Column::Num(r)) => Column::Num(&l * &r),
+                (BinaryOp::Div, Column::Num(l), Column::Num(r)) => Column::Num(&l / &r),
+                (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div, l, r) => {
+                    bail!("cannot apply {:?} to a {} and a {}", op, l.kind(), r.kind())
+                }
+                (op, Column::Num(l), Column::Num(r)) => Column::Bool(compare(op, &l, &r)),
+                (op, Column::Str(l), Column::Str(r)) => Column::Bool(compare(op, &l, &r)),
+                (op, Column::Bool(l), Column::Bool(r)) => Column::Bool(compare(op, &l, &r)),
+                (op, l, r) => bail!("cannot apply {:?} to a {} and a {}", op, l.kind(), r.kind()),
+            },
+        })
+    }
+}
+
+/// Values of an expression on all rows of a data frame.
+enum Column {
+    /// Null on every row.
+    Null,
+    Bool(BooleanChunked),
+    Num(Float64Chunked),
+    Str(Utf8Chunked),
+}
+
+impl Column {
+    fn kind(&self) -> &'static str {
+        match self {
+            Column::Null => "null",
+            Column::Bool(_) => "boolean",
+            Column::Num(_) => "number",
+            Column::Str(_) => "string",
+        }
+    }
+
+    /// The column as a mask, with null as false.
+    fn truthy(self, height: usize) -> Result<BooleanChunked> {
+        match self {
+            Column::Null => Ok(BooleanChunked::full("", false, height)),
+            Column::Bool(v) => Ok(v.fill_null_with_values(false)?),
+            v => bail!("expect a boolean, got a {}", v.kind()),
+        }
+    }
+}
+
+fn compare<T>(op: BinaryOp, l: &ChunkedArray<T>, r: &ChunkedArray<T>) -> BooleanChunked
+where
+    for<'a> ChunkedArray<T>: ChunkCompare<&'a ChunkedArray<T>>,
+{
+    // polars broadcasts a single row, comparing it to null as false rather than null.
+    if l.len() == 1 && l.null_count() + r.null_count() > 0 {
+        return BooleanChunked::full_null("", 1);
+    }
+    match op {
+        BinaryOp::Eq => l.equal(r),
+        BinaryOp::NotEq => l.not_equal(r),
+        BinaryOp::Lt => l.lt(r),
+        BinaryOp::LtEq => l.lt_eq(r),
+        BinaryOp::Gt => l.gt(r),
+        BinaryOp::GtEq => l.gt_eq(r),
+        _ => unreachable!(),
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Num(v) => write!(f, "{:?}", v),
+            Value::Str(v) => write!(f, "'{}'", v),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(c) => write!(f, "`{}`", c),
+            Expr::Literal(v) => write!(f, "{}", v),
+            Expr::Unary(UnaryOp::Neg, e) => write!(f, "-({})", e),
+            Expr::Unary(UnaryOp::Not, e) => write!(f, "!({})", e),
+            Expr::Binary(op, l, r) => {
+                let op = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Eq => "==",
+                    BinaryOp::NotEq => "!=",
+                    BinaryOp::Lt => "<",
+                    BinaryOp::LtEq => "<=",
+                    BinaryOp::Gt => ">",
+                    BinaryOp::GtEq => ">=",
+                    BinaryOp::And => "&&",
+                    BinaryOp::Or => "||",
+                };
+                write!(f, "({} {} {})", l, op, r)
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        let expr = parser.parse_or()?;
+        parser.skip_whitespace();
+        ensure!(
+            parser.chars.peek().is_none(),
+            "unexpected trailing input in `{}`",
+            s
+        );
+        Ok(expr)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    /// Consume `token` if the input continues with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let mut lookahead = self.chars.clone();
+        for c in token.chars() {
+            if lookahead.next() != Some(c) {
+                return false;
+            }
+        }
+        self.chars = lookahead;
+        true
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut ans = self.parse_and()?;
+        while self.eat("||") {
+            ans = Expr::Binary(BinaryOp::Or, Box::new(ans), Box::new(self.parse_and()?));
+        }
+        Ok(ans)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut ans = self.parse_not()?;
+        while self.eat("&&") {
+            ans = Expr::Binary(BinaryOp::And, Box::new(ans), Box::new(self.parse_not()?));
+        }
+        Ok(ans)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_not()?)));
+        }
+        self.parse_cmp()
+    }
+
+    fn parse_cmp(&mut self) -> Result<Expr> {
+        let l = self.parse_add()?;
+        // longer operators first.
+        let ops = [
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::NotEq),
+            ("<=", BinaryOp::LtEq),
+            (">=", BinaryOp::GtEq),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        for (token, op) in ops {
+            if self.eat(token) {
+                return Ok(Expr::Binary(op, Box::new(l), Box::new(self.parse_add()?)));
+            }
+        }
+        Ok(l)
+    }
+
+    fn parse_add(&mut self) -> Result<Expr> {
+        let mut ans = self.parse_mul()?;
+        loop {
+            let op = if self.eat("+") {
+                BinaryOp::Add
+            } else if self.eat("-") {
+                BinaryOp::Sub
+            } else {
+                return Ok(ans);
+            };
+            ans = Expr::Binary(op, Box::new(ans), Box::new(self.parse_mul()?));
+        }
+    }
+
+    fn parse_mul(&mut self) -> Result<Expr> {
+        let mut ans = self.parse_unary()?;
+        loop {
+            let op = if self.eat("*") {
+                BinaryOp::Mul
+            } else if self.eat("/") {
+                BinaryOp::Div
+            } else {
+                return Ok(ans);
+            };
+            ans = Expr::Binary(op, Box::new(ans), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.eat("-") {
+            return Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        self.skip_whitespace();
+        let c = *self.chars.peek().context("unexpected end of expression")?;
+        match c {
+            '(' => {
+                self.chars.next();
+                let ans = self.parse_or()?;
+                ensure!(self.eat(")"), "expect `)`");
+                Ok(ans)
+            }
+            '`' | '\'' | '"' => {
+                self.chars.next();
+                let mut s = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => s.push(ch),
+                        None => bail!("unterminated {}", c),
+                    }
+                }
+                Ok(if c == '`' {
+                    Expr::Column(s)
+                } else {
+                    Expr::Literal(Value::Str(s))
+                })
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut s = String::new();
+                while let Some(ch) = self.chars.next_if(|ch| ch.is_ascii_digit() || *ch == '.') {
+                    s.push(ch);
+                }
+                Ok(Expr::Literal(Value::Num(
+                    s.parse().with_context(|| format!("invalid number {}", s))?,
+                )))
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = String::new();
+                while let Some(ch) = self
+                    .chars
+                    .next_if(|ch| ch.is_alphanumeric() || *ch == '_' || *ch == ':')
+                {
+                    s.push(ch);
+                }
+                Ok(match s.as_str() {
+                    "true" => Expr::Literal(Value::Bool(true)),
+                    "false" => Expr::Literal(Value::Bool(false)),
+                    "null" => Expr::Literal(Value::Null),
+                    _ => Expr::Column(s),
+                })
+            }
+            c => bail!("unexpected `{}`", c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let expr: Expr = "-a + b * 2 >= 3 && !(`c d` == 'x') || null"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "((((-(`a`) + (`b` * 2.0)) >= 3.0) && !((`c d` == 'x'))) || null)"
+        );
+        assert_eq!(expr.to_string().parse::<Expr>().unwrap(), expr);
+        assert_eq!(
+            expr.columns().into_iter().collect::<Vec<_>>(),
+            vec!["a", "b", "c d"]
+        );
+        assert!("a +".parse::<Expr>().is_err());
+        assert!("a b".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let df = DataFrame::new(vec![
+            Series::new("a", &[Some(1i64), Some(2), None]),
+            Series::new("b", &["x", "y", "z"]),
+        ])
+        .unwrap();
+        let weight: Expr = "a * 2 + 1".parse().unwrap();
+        assert_eq!(weight.eval_f64(&df).unwrap(), vec![3., 5., 0.]);
+        let pred: Expr = "a > 1 || b == 'x'".parse().unwrap();
+        assert_eq!(
+            pred.eval_mask(&df).unwrap().into_iter().collect::<Vec<_>>(),
+            vec![Some(true), Some(true), Some(false)]
+        );
+        assert!("b + 1".parse::<Expr>().unwrap().eval(&df).is_err());
+        assert!("b".parse::<Expr>().unwrap().eval_f64(&df).is_err());
+        // null propagates through comparison, also on a single row.
+        let not_pred: Expr = "!(a > 1) || a == null".parse().unwrap();
+        for df in [df.clone(), df.slice(2, 1)] {
+            let mask = not_pred.eval_mask(&df).unwrap();
+            assert_eq!(mask.into_iter().last(), Some(Some(false)));
+        }
+    }
+}
@@ -1,83 +1,605 @@
+use crate::{expr::Expr, DataSet, ROW_ID_COL_NAME};
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::RwLock,
+};
 
-#[derive(Debug, Clone)]
+/// How a join step treats rows without a match.
+///
+/// Tables on the null-extended side of a tuple contribute no row and therefore impose
+/// no condition on the sellers that can supply the tuple.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinKind {
+    #[default]
+    Inner,
+    /// Keep rows of the joined result without a match in `table_to_join`.
+    Left,
+    /// Keep unmatched rows on both sides.
+    Outer,
+    /// Keep rows of the joined result with at least one match in `table_to_join`, without
+    /// taking any of its columns. Any one matching row is enough to supply the tuple.
+    Semi,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct JoinStep {
-    pub table_to_join: &'static str,
-    pub left_join_keys: Vec<&'static str>,
-    pub right_join_keys: Vec<&'static str>,
+    /// Table owning the left join keys. When absent, the keys are looked up by name
+    /// among all tables joined so far.
+    #[serde(default)]
+    pub left_table: Option<String>,
+    pub table_to_join: String,
+    pub left_join_keys: Vec<String>,
+    pub right_join_keys: Vec<String>,
+    #[serde(default)]
+    pub join_type: JoinKind,
+}
+
+impl JoinStep {
+    pub fn new(table_to_join: &str, left_join_keys: &[&str], right_join_keys: &[&str]) -> Self {
+        Self {
+            left_table: None,
+            table_to_join: table_to_join.to_string(),
+            left_join_keys: left_join_keys.iter().map(|k| k.to_string()).collect(),
+            right_join_keys: right_join_keys.iter().map(|k| k.to_string()).collect(),
+            join_type: JoinKind::Inner,
+        }
+    }
+
+    pub fn with_join_type(mut self, join_type: JoinKind) -> Self {
+        self.join_type = join_type;
+        self
+    }
+
+    pub fn with_left_table(mut self, left_table: &str) -> Self {
+        self.left_table = Some(left_table.to_string());
+        self
+    }
 }
 
-#[derive(Debug, Clone)]
+/// A join graph rooted at `init_table`.
+///
+/// Tables are the nodes and every step is an edge connecting `table_to_join` to a table
+/// joined before it, so star and snowflake schemas can be expressed by pointing several
+/// steps at the same `left_table`. The graph must be a tree.
+///
+/// The plan may also describe the query a buyer purchases: `filter` keeps the joined tuples
+/// satisfying a predicate, and `projection` keeps only some columns, with duplicates dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct JoinPlan {
-    pub init_table: &'static str,
+    pub init_table: String,
     pub steps: Vec<JoinStep>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<Expr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub projection: Option<Vec<String>>,
+}
+
+impl JoinPlan {
+    pub fn new(init_table: &str, steps: Vec<JoinStep>) -> Self {
+        Self {
+            init_table: init_table.to_string(),
+            steps,
+            filter: None,
+            projection: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Expr) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_projection(mut self, projection: &[&str]) -> Self {
+        self.projection = Some(projection.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// All tables in the join graph, starting with `init_table`.
+    pub fn tables(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.init_table.as_str())
+            .chain(self.steps.iter().map(|s| s.table_to_join.as_str()))
+    }
+
+    /// Whether some step keeps unmatched rows, producing null-extended tuples.
+    pub fn has_outer_join(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|s| matches!(s.join_type, JoinKind::Left | JoinKind::Outer))
+    }
+
+    /// Tables joined by a semi join. Their row ids only witness a match, so tuples that
+    /// differ in them alone are the same tuple.
+    pub fn semi_tables(&self) -> impl Iterator<Item = &str> {
+        self.steps
+            .iter()
+            .filter(|s| s.join_type == JoinKind::Semi)
+            .map(|s| s.table_to_join.as_str())
+    }
+
+    /// Tables with a row in every tuple of the join result: the init table and the tables
+    /// joined to one of them by an inner join. A step without `left_table` only counts when no
+    /// step null-extends tuples.
+    pub fn inner_tables(&self) -> HashSet<&str> {
+        let outer = self.has_outer_join();
+        let mut ans = HashSet::from([self.init_table.as_str()]);
+        // steps may be listed before the step joining their left table.
+        loop {
+            let len = ans.len();
+            for step in &self.steps {
+                let left_inner = match &step.left_table {
+                    Some(table) => ans.contains(table.as_str()),
+                    None => !outer,
+                };
+                if step.join_type == JoinKind::Inner && left_inner {
+                    ans.insert(step.table_to_join.as_str());
+                }
+            }
+            if ans.len() == len {
+                return ans;
+            }
+        }
+    }
+
+    /// Check that the join graph is a tree, i.e. every table is joined once and following
+    /// `left_table` always leads back to `init_table`.
+    pub fn check_acyclic(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for table in self.tables() {
+            ensure!(
+                seen.insert(table),
+                "table `{}` is joined more than once; the join graph must be acyclic",
+                table
+            );
+        }
+
+        let parents: HashMap<&str, Option<&str>> = self
+            .steps
+            .iter()
+            .map(|s| (s.table_to_join.as_str(), s.left_table.as_deref()))
+            .collect();
+        for step in &self.steps {
+            let mut table = step.table_to_join.as_str();
+            let mut visited = HashSet::new();
+            while let Some(Some(parent)) = parents.get(table) {
+                ensure!(
+                    visited.insert(table),
+                    "join graph has a cycle through `{}`",
+                    step.table_to_join
+                );
+                ensure!(
+                    seen.contains(parent),
+                    "step `{}`: left table `{}` is not part of the join graph",
+                    table,
+                    parent
+                );
+                table = parent;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pick an execution order for the join graph.
+    ///
+    /// Steps run in the listed order unless their left side is not joined yet, in which
+    /// case they are deferred. Return each step along with its left join keys resolved to
+    /// column names of the running join result, and the columns of the final join result.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn ordered_steps(
+        &self,
+        columns_fn: impl Fn(&str) -> Option<Vec<String>>,
+    ) -> Result<(Vec<(&JoinStep, Vec<String>)>, HashSet<String>)> {
+        self.check_acyclic()?;
+        let init_columns = columns_fn(&self.init_table).context("cannot find init table")?;
+        let mut joined = JoinedColumns::new(&self.init_table, init_columns);
+
+        let mut pending: Vec<&JoinStep> = self.steps.iter().collect();
+        let mut ans = Vec::with_capacity(pending.len());
+        while !pending.is_empty() {
+            let (i, left_keys) = pending
+                .iter()
+                .enumerate()
+                .find_map(|(i, step)| joined.resolve_keys(step).map(|keys| (i, keys)))
+                .with_context(|| {
+                    format!(
+                        "cannot join [{}]: left join keys not found in joined result",
+                        pending.iter().map(|s| s.table_to_join.as_str()).join(", ")
+                    )
+                })?;
+            let step = pending.remove(i);
+            let right_columns =
+                columns_fn(&step.table_to_join).context("cannot find table to join")?;
+            joined.add(step, &left_keys, right_columns);
+            ans.push((step, left_keys));
+        }
+
+        let mut result_columns = joined.result_columns;
+        if result_columns.remove(ROW_ID_COL_NAME) {
+            result_columns.insert(format!("{}:{}", ROW_ID_COL_NAME, self.init_table));
+        }
+        Ok((ans, result_columns))
+    }
+
+    /// Check that every table and join key referenced by the plan exists in `dataset`.
+    ///
+    /// All problems are collected and reported together.
+    pub fn validate(&self, dataset: &DataSet) -> Result<()> {
+        let columns_of = |table_name: &str| -> Option<Vec<String>> {
+            dataset.tables.get(table_name).map(|t| {
+                t.df.get_column_names()
+                    .into_iter()
+                    .map(|c| c.to_string())
+                    .collect()
+            })
+        };
+
+        let mut errors = vec![];
+        if let Err(e) = self.check_acyclic() {
+            errors.push(e.to_string());
+        }
+        if columns_of(&self.init_table).is_none() {
+            errors.push(format!("init table `{}` not found", self.init_table));
+        }
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let step_name = format!("step #{} (`{}`)", i, step.table_to_join);
+            if step.left_join_keys.len() != step.right_join_keys.len() {
+                errors.push(format!(
+                    "{}: {} left join keys but {} right join keys",
+                    step_name,
+                    step.left_join_keys.len(),
+                    step.right_join_keys.len(),
+                ));
+            }
+
+            if let Some(left_table) = &step.left_table {
+                match columns_of(left_table) {
+                    Some(left_columns) => {
+                        for key in &step.left_join_keys {
+                            if !left_columns.contains(key) {
+                                errors.push(format!(
+                                    "{}: left join key `{}` not found in table `{}`",
+                                    step_name, key, left_table
+                                ));
+                            }
+                        }
+                    }
+                    None => errors.push(format!(
+                        "{}: left table `{}` not found",
+                        step_name, left_table
+                    )),
+                }
+            }
+
+            match columns_of(&step.table_to_join) {
+                Some(right_columns) => {
+                    for key in &step.right_join_keys {
+                        if !right_columns.contains(key) {
+                            errors.push(format!(
+                                "{}: right join key `{}` not found in table `{}`",
+                                step_name, key, step.table_to_join
+                            ));
+                        }
+                    }
+                }
+                None => errors.push(format!("{}: table not found", step_name)),
+            }
+        }
+
+        // only resolve the graph once each step is well-formed on its own.
+        if errors.is_empty() {
+            match self.ordered_steps(columns_of) {
+                Ok((_, result_columns)) => {
+                    let filter_columns = self.filter.iter().flat_map(|f| f.columns());
+                    for column in filter_columns {
+                        if !result_columns.contains(column) {
+                            errors.push(format!("filter column `{}` not found", column));
+                        }
+                    }
+                    for column in self.projection.iter().flatten() {
+                        if !result_columns.contains(column) {
+                            errors.push(format!("projection column `{}` not found", column));
+                        }
+                    }
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        ensure!(
+            errors.is_empty(),
+            "invalid join plan for {}:\n  {}",
+            dataset.name,
+            errors.join("\n  ")
+        );
+        Ok(())
+    }
+}
+
+/// Tracks the name under which each table's columns appear in the running join result.
+///
+/// Right join keys are dropped by the join and live on as the matching left join key,
+/// while other columns clashing with an existing name get a `:<table>` suffix.
+#[derive(Debug)]
+pub(crate) struct JoinedColumns {
+    // (table, column) -> column in the join result
+    columns: HashMap<(String, String), String>,
+    result_columns: HashSet<String>,
+}
+
+impl JoinedColumns {
+    fn new(table: &str, columns: Vec<String>) -> Self {
+        Self {
+            columns: columns
+                .iter()
+                .map(|c| ((table.to_string(), c.clone()), c.clone()))
+                .collect(),
+            result_columns: columns.into_iter().collect(),
+        }
+    }
+
+    fn resolve(&self, table: Option<&str>, column: &str) -> Option<String> {
+        match table {
+            Some(table) => self
+                .columns
+                .get(&(table.to_string(), column.to_string()))
+                .cloned(),
+            None => {
+                let mut candidates = self
+                    .columns
+                    .iter()
+                    .filter(|((_, c), _)| c == column)
+                    .map(|(_, r)| r)
+                    .unique();
+                match (candidates.next(), candidates.next()) {
+                    (Some(r), None) => Some(r.clone()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn resolve_keys(&self, step: &JoinStep) -> Option<Vec<String>> {
+        step.left_join_keys
+            .iter()
+            .map(|k| self.resolve(step.left_table.as_deref(), k))
+            .collect()
+    }
+
+    fn add(&mut self, step: &JoinStep, left_keys: &[String], right_columns: Vec<String>) {
+        let table = &step.table_to_join;
+        let mut new_columns = vec![];
+        for c in right_columns {
+            if step.join_type == JoinKind::Semi
+                && c != ROW_ID_COL_NAME
+                && !step.right_join_keys.contains(&c)
+            {
+                continue;
+            }
+
+            let result_column = match step.right_join_keys.iter().position(|k| *k == c) {
+                Some(i) => left_keys[i].clone(),
+                None if self.result_columns.contains(&c) => format!("{}:{}", c, table),
+                None => c.clone(),
+            };
+            new_columns.push(result_column.clone());
+            self.columns.insert((table.clone(), c), result_column);
+        }
+        self.result_columns.extend(new_columns);
+    }
+}
+
+/// Join plans by name, consisting of the built-in plans and any loaded from plan files.
+#[derive(Debug, Clone, Default)]
+pub struct PlanRegistry {
+    plans: HashMap<String, JoinPlan>,
 }
 
-pub static PLANS: Lazy<HashMap<&'static str, JoinPlan>> = Lazy::new(|| {
-    let mut plans = HashMap::new();
-    plans.insert(
-        "world",
-        JoinPlan {
-            init_table: "city",
-            steps: vec![
-                JoinStep {
-                    table_to_join: "country",
-                    left_join_keys: vec!["CountryCode"],
-                    right_join_keys: vec!["Code"],
-                },
-                JoinStep {
-                    table_to_join: "countrylanguage",
-                    left_join_keys: vec!["Code"],
-                    right_join_keys: vec!["CountryCode"],
-                },
+impl PlanRegistry {
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        registry.insert(
+            "world",
+            JoinPlan::new(
+                "city",
+                vec![
+                    JoinStep::new("country", &["CountryCode"], &["Code"]),
+                    JoinStep::new("countrylanguage", &["Code"], &["CountryCode"]),
+                ],
+            ),
+        );
+        registry.insert(
+            "tpch",
+            JoinPlan::new(
+                "region",
+                vec![
+                    JoinStep::new("nation", &["r_regionkey"], &["n_regionkey"]),
+                    JoinStep::new("supplier", &["n_nationkey"], &["s_nationkey"]),
+                    JoinStep::new("partsupp", &["s_suppkey"], &["ps_suppkey"]),
+                    JoinStep::new("part", &["ps_partkey"], &["p_partkey"]),
+                    JoinStep::new(
+                        "lineitem",
+                        &["p_partkey", "ps_suppkey"],
+                        &["l_partkey", "l_suppkey"],
+                    ),
+                    JoinStep::new("orders", &["l_orderkey"], &["o_orderkey"]),
+                    JoinStep::new("customer", &["o_custkey"], &["c_custkey"]),
+                ],
+            ),
+        );
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&JoinPlan> {
+        self.plans.get(name)
+    }
+
+    /// Register `plan` under `name`, returning the plan it replaces.
+    pub fn insert(&mut self, name: impl Into<String>, plan: JoinPlan) -> Option<JoinPlan> {
+        self.plans.insert(name.into(), plan)
+    }
+
+    /// Merge plans from a TOML or JSON file.
+    ///
+    /// The file holds either a table of named plans, or a single plan which is
+    /// registered under `default_name`. Plans in the file override plans of the same name.
+    pub fn load_file(&mut self, path: impl AsRef<Path>, default_name: &str) -> Result<()> {
+        #[derive(Debug, Deserialize)]
+        #[serde(untagged)]
+        enum PlanFile {
+            Single(JoinPlan),
+            Named(HashMap<String, JoinPlan>),
+        }
+
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read plan file {}", path.display()))?;
+        let plan_file: PlanFile = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&content)?,
+            Some("json") => serde_json::from_str(&content)?,
+            _ => bail!(
+                "unknown plan file format {}. accepted extensions: [toml, json]",
+                path.display()
+            ),
+        };
+        let plans = match plan_file {
+            PlanFile::Single(plan) => HashMap::from([(default_name.to_string(), plan)]),
+            PlanFile::Named(plans) => plans,
+        };
+
+        for (name, plan) in plans {
+            if self.insert(name.clone(), plan).is_some() {
+                warn!("join plan {} is overridden by {}", name, path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+pub static PLANS: Lazy<RwLock<PlanRegistry>> = Lazy::new(|| RwLock::new(PlanRegistry::builtin()));
+
+/// Look up a join plan in the global registry.
+pub fn get_plan(name: &str) -> Result<JoinPlan> {
+    PLANS
+        .read()
+        .unwrap()
+        .get(name)
+        .cloned()
+        .with_context(|| format!("cannot find join plan for {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::load_world;
+
+    #[test]
+    fn test_parse() {
+        let plans: HashMap<String, JoinPlan> = toml::from_str(
+            r#"
+            [world]
+            init_table = "city"
+
+            [[world.steps]]
+            table_to_join = "country"
+            left_join_keys = ["CountryCode"]
+            right_join_keys = ["Code"]
+
+            [[world.steps]]
+            table_to_join = "countrylanguage"
+            left_join_keys = ["Code"]
+            right_join_keys = ["CountryCode"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            plans["world"],
+            *PlanRegistry::builtin().get("world").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let plan: JoinPlan = serde_json::from_str(
+            r#"{
+                "init_table": "city",
+                "steps": [],
+                "filter": "Population > 1000000",
+                "projection": ["CountryCode"]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            plan,
+            JoinPlan::new("city", vec![])
+                .with_filter("Population > 1000000".parse().unwrap())
+                .with_projection(&["CountryCode"])
+        );
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(serde_json::from_str::<JoinPlan>(&json).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_validate() {
+        let world = load_world();
+        get_plan("world").unwrap().validate(&world).unwrap();
+
+        let plan = JoinPlan::new(
+            "city",
+            vec![
+                JoinStep::new("country", &["CountryCode"], &["Code", "Name"]),
+                JoinStep::new("language", &["Code"], &["CountryCode"]),
             ],
-        },
-    );
-
-    plans.insert(
-        "tpch",
-        JoinPlan {
-            init_table: "region",
-            steps: vec![
-                JoinStep {
-                    table_to_join: "nation",
-                    left_join_keys: vec!["r_regionkey"],
-                    right_join_keys: vec!["n_regionkey"],
-                },
-                JoinStep {
-                    table_to_join: "supplier",
-                    left_join_keys: vec!["n_nationkey"],
-                    right_join_keys: vec!["s_nationkey"],
-                },
-                JoinStep {
-                    table_to_join: "partsupp",
-                    left_join_keys: vec!["s_suppkey"],
-                    right_join_keys: vec!["ps_suppkey"],
-                },
-                JoinStep {
-                    table_to_join: "part",
-                    left_join_keys: vec!["ps_partkey"],
-                    right_join_keys: vec!["p_partkey"],
-                },
-                JoinStep {
-                    table_to_join: "lineitem",
-                    left_join_keys: vec!["p_partkey", "ps_suppkey"],
-                    right_join_keys: vec!["l_partkey", "l_suppkey"],
-                },
-                JoinStep {
-                    table_to_join: "orders",
-                    left_join_keys: vec!["l_orderkey"],
-                    right_join_keys: vec!["o_orderkey"],
-                },
-                JoinStep {
-                    table_to_join: "customer",
-                    left_join_keys: vec!["o_custkey"],
-                    right_join_keys: vec!["c_custkey"],
-                },
+        );
+        let err = plan.validate(&world).unwrap_err().to_string();
+        assert!(err.contains("1 left join keys but 2 right join keys"));
+        assert!(err.contains("step #1 (`language`): table not found"));
+
+        let plan = get_plan("world")
+            .unwrap()
+            .with_filter("Populaton > 0".parse().unwrap())
+            .with_projection(&["Language", "Name:country"]);
+        let err = plan.validate(&world).unwrap_err().to_string();
+        assert!(err.contains("filter column `Populaton` not found"));
+        assert!(!err.contains("projection column"));
+    }
+
+    #[test]
+    fn test_check_acyclic() {
+        let star = JoinPlan::new(
+            "city",
+            vec![
+                JoinStep::new("countrylanguage", &["CountryCode"], &["CountryCode"])
+                    .with_left_table("city"),
+                JoinStep::new("country", &["CountryCode"], &["Code"]).with_left_table("city"),
             ],
-        },
-    );
+        );
+        star.check_acyclic().unwrap();
 
-    plans
-});
+        let cycle = JoinPlan::new(
+            "city",
+            vec![
+                JoinStep::new("country", &["Code"], &["CountryCode"])
+                    .with_left_table("countrylanguage"),
+                JoinStep::new("countrylanguage", &["CountryCode"], &["Code"])
+                    .with_left_table("country"),
+            ],
+        );
+        assert!(cycle.check_acyclic().is_err());
+
+        let twice = JoinPlan::new(
+            "city",
+            vec![
+                JoinStep::new("country", &["CountryCode"], &["Code"]),
+                JoinStep::new("city", &["Capital"], &["ID"]),
+            ],
+        );
+        assert!(twice.check_acyclic().is_err());
+    }
+}
@@ -4,15 +4,29 @@ extern crate tracing;
 pub mod seller;
 pub use seller::*;
 
+pub mod bitset;
+pub use bitset::*;
+
+pub mod manifest;
+pub use manifest::*;
+
 pub mod table;
 pub use table::*;
 
+pub mod ownership;
+pub use ownership::*;
+
 pub mod dataset;
 pub use dataset::*;
 
 pub mod join_plan;
 pub use join_plan::*;
 
+pub mod expr;
+
+pub mod utility;
+pub use utility::*;
+
 pub mod alg;
 pub use alg::*;
 
@@ -1,10 +1,10 @@
 #[macro_use]
 extern crate tracing;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use serde_json::json;
 use shapley_value::*;
-use std::{fs::File, io::BufWriter, path::PathBuf};
+use std::{fs::File, io::BufWriter, path::PathBuf, time::Duration};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -13,7 +13,7 @@ struct Opts {
     #[structopt(short = "-d", long)]
     name: String,
 
-    /// Input csv directory
+    /// Input directory of the tables
     #[structopt(short = "i", long, parse(from_os_str))]
     csv_dir: PathBuf,
 
@@ -21,13 +21,48 @@ struct Opts {
     #[structopt(short, long, parse(from_os_str))]
     meta_dir: PathBuf,
 
+    /// Join plan file (toml or json), merged with the built-in plans
+    #[structopt(long, parse(from_os_str))]
+    plan: Option<PathBuf>,
+
+    /// Name of the join plan, if not the dataset name
+    #[structopt(long)]
+    plan_name: Option<String>,
+
     /// Output file
     #[structopt(short, long, parse(from_os_str))]
-    output: PathBuf,
+    output: Option<PathBuf>,
 
     /// Scheme name. accepted values: [trad, perm, ours]
     #[structopt(short, long)]
-    scheme: String,
+    scheme: Option<String>,
+
+    /// Utility. accepted values: [count, sum:<column>, expr:<expression>, distinct:<columns>]
+    #[structopt(short, long, default_value = "count")]
+    utility: String,
+
+    /// Value to compute: shapley, banzhaf or beta:<a>,<b>
+    #[structopt(long, default_value = "shapley")]
+    value: String,
+
+    /// Also compute the Shapley interaction index of every seller pair (for proposed and
+    /// permutation)
+    #[structopt(long)]
+    interactions: bool,
+
+    /// Stream the value of every seller in every tuple to this csv file, or to a directory of
+    /// parquet files (for proposed)
+    #[structopt(long, parse(from_os_str))]
+    attribution: Option<PathBuf>,
+
+    /// Break the value of every seller down by the tables and rows it owns (for proposed)
+    #[structopt(long)]
+    contributions: bool,
+
+    /// Join the init table in chunks whose joined tuples take about this much memory, e.g.
+    /// 4G (for proposed)
+    #[structopt(long)]
+    memory_limit: Option<String>,
 
     /// Scale (for proposed)
     #[structopt(long, default_value = "1")]
@@ -37,49 +72,186 @@ struct Opts {
     #[structopt(short = "l", long)]
     sample_size: Option<usize>,
 
+    /// Keep sampling until the standard error of every seller is at most this (for permutation).
+    /// The sample size, if given, caps the number of samples
+    #[structopt(long)]
+    tolerance: Option<f64>,
+
+    /// Stop sampling after this many seconds (for permutation)
+    #[structopt(long)]
+    time_budget: Option<f64>,
+
+    /// Minimum number of samples before checking the tolerance (for permutation)
+    #[structopt(long, default_value = "16")]
+    min_sample_size: usize,
+
+    /// Stop walking a permutation once its prefix is within this of the grand coalition's
+    /// utility (for permutation, with a monotone utility: count or distinct)
+    #[structopt(long)]
+    truncation: Option<f64>,
+
+    /// Sampling estimator: perm, antithetic or stratified (for permutation)
+    #[structopt(long, default_value = "perm")]
+    estimator: String,
+
+    /// Random seed (for permutation). A random one is drawn and recorded if absent
+    #[structopt(long)]
+    seed: Option<u64>,
+
     /// Number of threads
     #[structopt(short, long)]
     num_threads: Option<usize>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Check the ownership metadata and the join plan of the dataset, without computing any
+    /// value
+    Validate,
 }
 
 fn main() -> Result<()> {
     utils::init_tracing_subscriber("info")?;
-    let opts = Opts::from_args();
+    let mut opts = Opts::from_args();
+    let seed = *opts.seed.get_or_insert_with(rand::random);
     info!("opts: {:#?}", opts);
     utils::setup_rayon(opts.num_threads)?;
 
+    let plan_name = opts.plan_name.clone().unwrap_or_else(|| opts.name.clone());
+    if let Some(plan) = &opts.plan {
+        PLANS.write().unwrap().load_file(plan, &plan_name)?;
+    }
+
+    if let Some(Command::Validate) = opts.command {
+        return polars_core::POOL.install(|| {
+            validate_ownership(&opts.csv_dir, &opts.meta_dir)?;
+            DataSet::load(
+                &opts.name,
+                &opts.csv_dir,
+                &opts.meta_dir,
+                get_plan(&plan_name)?,
+            )?;
+            info!("{} is valid", opts.name);
+            Ok(())
+        });
+    }
+    let scheme = opts.scheme.clone().context("need a scheme")?;
+    let output = opts.output.clone().context("need an output file")?;
+
     let result = polars_core::POOL.install(|| {
-        let dataset = DataSet::load(&opts.name, &opts.csv_dir, &opts.meta_dir)?;
+        let dataset = DataSet::load(
+            &opts.name,
+            &opts.csv_dir,
+            &opts.meta_dir,
+            get_plan(&plan_name)?,
+        )?;
+        let utility = parse_utility(&opts.utility)?;
+        let utility = utility.as_ref();
+        let value: Semivalue = opts.value.parse()?;
+        let sampling_opts = alg::permutation::SamplingOpts {
+            value,
+            estimator: opts.estimator.parse()?,
+            truncation: opts.truncation,
+            seed,
+            interactions: opts.interactions,
+        };
+
+        ensure!(
+            (opts.attribution.is_none() && !opts.contributions)
+                || matches!(scheme.as_str(), "proposed" | "ours"),
+            "attribution and contributions need the proposed scheme"
+        );
 
-        let result = match opts.scheme.as_str() {
-            "traditional" | "trad" => alg::traditional::traditional_scheme(&dataset)?,
+        let result = match scheme.as_str() {
+            "traditional" | "trad" => {
+                ensure!(
+                    !opts.interactions,
+                    "interactions need the proposed or permutation scheme"
+                );
+                alg::traditional::traditional_scheme(&dataset, utility, &value)?
+            }
+            "permutation" | "perm" if opts.tolerance.is_some() || opts.time_budget.is_some() => {
+                let convergence = alg::permutation::Convergence {
+                    tolerance: opts.tolerance.unwrap_or_default(),
+                    time_budget: opts.time_budget.map(Duration::from_secs_f64),
+                    min_sample_size: opts.min_sample_size,
+                    max_sample_size: opts.sample_size,
+                };
+                alg::permutation::permutation_scheme_adaptive(
+                    &dataset,
+                    utility,
+                    &convergence,
+                    &sampling_opts,
+                )?
+            }
             "permutation" | "perm" => alg::permutation::permutation_scheme(
                 &dataset,
-                opts.sample_size.context("need sample size")?,
+                utility,
+                opts.sample_size
+                    .context("need sample size, tolerance or time budget")?,
+                &sampling_opts,
             )?,
-            "proposed" | "ours" => alg::proposed::proposed_scheme(&dataset, opts.scale)?,
+            "proposed" | "ours" => {
+                let proposed_opts = alg::proposed::ProposedOpts {
+                    value,
+                    scale: opts.scale,
+                    interactions: opts.interactions,
+                    attribution: opts.attribution.clone(),
+                    contributions: opts.contributions,
+                    memory_limit: opts
+                        .memory_limit
+                        .as_deref()
+                        .map(utils::parse_size)
+                        .transpose()?,
+                };
+                alg::proposed::proposed_scheme(&dataset, utility, &proposed_opts)?
+            }
             _ => bail!("Unknown scheme. accepted values: [trad, perm, ours]"),
         };
 
-        Ok(result)
+        // report sellers by their external names.
+        let registry = &dataset.registry;
+        let sellers: Vec<SellerInfo> = registry.iter().map(|(_, info)| info.clone()).collect();
+        Ok((
+            result.map_sellers(|id| registry.name(id).to_string()),
+            sellers,
+        ))
     })?;
+    let (result, sellers) = result;
 
     let mut result_json = serde_json::to_value(result)?;
     result_json.as_object_mut().unwrap().append(
         json!({
             "dataset": opts.name,
-            "scheme": opts.scheme,
+            "sellers": sellers,
+            "scheme": scheme,
+            "utility": opts.utility,
+            "value": opts.value,
+            "interactions": opts.interactions,
             "csv_dir": opts.csv_dir,
             "meta_dir": opts.meta_dir,
+            "plan": opts.plan,
+            "plan_name": plan_name,
             "num_threads": opts.num_threads,
             "scale": opts.scale,
+            "attribution": opts.attribution,
+            "contributions": opts.contributions,
+            "memory_limit": opts.memory_limit,
             "sample_size": opts.sample_size,
+            "estimator": opts.estimator,
+            "seed": opts.seed,
+            "tolerance": opts.tolerance,
+            "time_budget": opts.time_budget,
+            "truncation": opts.truncation,
         })
         .as_object_mut()
         .unwrap(),
     );
 
-    let out = BufWriter::new(File::create(&opts.output)?);
+    let out = BufWriter::new(File::create(&output)?);
     serde_json::to_writer(out, &result_json)?;
 
     Ok(())
@@ -0,0 +1,191 @@
+use anyhow::{bail, ensure, Context, Result};
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+/// How the tables of a dataset are read, from `manifest.json` in its data dir if any.
+///
+/// ```json
+/// {"tables": {"city": {"path": "city.tsv", "format": "csv", "delimiter": "\t",
+///                      "has_header": true, "dtypes": {"ID": "u64"}}}}
+/// ```
+///
+/// Data files not listed are tables too, named by their stems and read with the defaults.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub tables: BTreeMap<String, TableOptions>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableOptions {
+    /// Data file of the table, relative to the data dir. By default the data file named after
+    /// the table.
+    pub path: Option<PathBuf>,
+    /// Format of the data file. By default detected from its extension.
+    pub format: Option<TableFormat>,
+    /// Field delimiter of a csv file. `,` by default.
+    pub delimiter: Option<char>,
+    /// Whether a csv file starts with a header. `true` by default.
+    pub has_header: Option<bool>,
+    /// Types of columns, overriding those inferred from a csv file or stored in the file.
+    pub dtypes: HashMap<String, ColumnType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+    /// Arrow IPC.
+    Ipc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Bool,
+    I32,
+    I64,
+    U32,
+    U64,
+    F32,
+    F64,
+    Str,
+}
+
+impl TableFormat {
+    /// Format of a data file by its extension.
+    pub fn of(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "parquet" => Some(Self::Parquet),
+            "ipc" | "arrow" | "feather" => Some(Self::Ipc),
+            _ => None,
+        }
+    }
+}
+
+impl From<ColumnType> for DataType {
+    fn from(t: ColumnType) -> Self {
+        match t {
+            ColumnType::Bool => DataType::Boolean,
+            ColumnType::I32 => DataType::Int32,
+            ColumnType::I64 => DataType::Int64,
+            ColumnType::U32 => DataType::UInt32,
+            ColumnType::U64 => DataType::UInt64,
+            ColumnType::F32 => DataType::Float32,
+            ColumnType::F64 => DataType::Float64,
+            ColumnType::Str => DataType::Utf8,
+        }
+    }
+}
+
+impl Manifest {
+    /// The manifest of `data_dir`, empty if it has none.
+    pub fn load(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let path = data_dir.as_ref().join("manifest.json");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        info!("load manifest from {}...", path.display());
+        let file = File::open(&path).with_context(|| path.display().to_string())?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    /// Name, data file and read options of every table of `data_dir`, by name.
+    pub fn table_files(
+        &self,
+        data_dir: impl AsRef<Path>,
+    ) -> Result<Vec<(String, PathBuf, TableOptions)>> {
+        let data_dir = data_dir.as_ref();
+        let mut files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in fs::read_dir(data_dir).with_context(|| data_dir.display().to_string())? {
+            let path = entry?.path();
+            if path.is_file() && TableFormat::of(&path).is_some() {
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                files.entry(name).or_default().push(path);
+            }
+        }
+
+        let mut ans = vec![];
+        for (name, options) in &self.tables {
+            let path = match &options.path {
+                Some(path) => data_dir.join(path),
+                None => match files.get(name).map(|paths| paths.as_slice()) {
+                    Some([path]) => path.clone(),
+                    Some(paths) => bail!("table {} has several data files: {:?}", name, paths),
+                    None => bail!("cannot find the data file of table {}", name),
+                },
+            };
+            ans.push((name.clone(), path, options.clone()));
+        }
+        for (name, paths) in files {
+            if self.tables.contains_key(&name) {
+                continue;
+            }
+            ensure!(
+                paths.len() == 1,
+                "table {} has several data files: {:?}",
+                name,
+                paths
+            );
+            ans.push((name, paths[0].clone(), TableOptions::default()));
+        }
+        ans.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(ans)
+    }
+}
+
+/// Read a csv, parquet or Arrow IPC data file.
+pub fn read_data_file(path: impl AsRef<Path>, options: &TableOptions) -> Result<DataFrame> {
+    let path = path.as_ref();
+    let format = match options.format {
+        Some(format) => format,
+        None => TableFormat::of(path)
+            .with_context(|| format!("unknown format of {}", path.display()))?,
+    };
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let dtypes: Vec<(&String, DataType)> = options
+        .dtypes
+        .iter()
+        .map(|(column, t)| (column, DataType::from(*t)))
+        .collect();
+    let mut df = match format {
+        TableFormat::Csv => {
+            let delimiter = options.delimiter.unwrap_or(',');
+            ensure!(
+                delimiter.is_ascii(),
+                "delimiter of {} is not ascii",
+                path.display()
+            );
+            let schema = Schema::new(
+                dtypes
+                    .iter()
+                    .map(|(column, dtype)| Field::new(column, dtype.clone()))
+                    .collect(),
+            );
+            CsvReader::new(file)
+                .with_delimiter(delimiter as u8)
+                .has_header(options.has_header.unwrap_or(true))
+                .with_dtypes(Some(&schema))
+                .finish()?
+        }
+        TableFormat::Parquet => ParquetReader::new(file).finish()?,
+        TableFormat::Ipc => IpcReader::new(file).finish()?,
+    };
+    if format != TableFormat::Csv {
+        for (column, dtype) in &dtypes {
+            let series = df.column(column)?.cast(dtype)?;
+            df.with_column(series)?;
+        }
+    }
+    Ok(df)
+}
@@ -0,0 +1,598 @@
+use crate::{
+    read_data_file, read_owners, table::SellerFile, utils::read_json, Manifest, Owners, RowId,
+    SellerRegistry, TableOptions, ROW_ID_COL_NAME,
+};
+use anyhow::{bail, ensure, Context, Result};
+use polars::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+/// Column of a data file listing the sellers of each row, separated by [`SELLER_SEP`], or by
+/// [`JOINT_SELLER_SEP`] if they own it jointly.
+pub const SELLERS_COL_NAME: &str = "_sellers";
+pub const SELLER_SEP: char = ';';
+pub const JOINT_SELLER_SEP: char = '&';
+
+/// Owners of every table.
+pub type Ownership = HashMap<String, Owners>;
+
+/// The consolidated ownership file of `meta_dir`, `ownership.parquet` or `ownership.csv`, if
+/// any.
+pub fn ownership_path(meta_dir: impl AsRef<Path>) -> Option<PathBuf> {
+    ["ownership.parquet", "ownership.csv"]
+        .iter()
+        .map(|f| meta_dir.as_ref().join(f))
+        .find(|path| path.exists())
+}
+
+/// Read a parquet or csv file of `(table, row_id, seller)` triples, with an optional boolean
+/// `joint` column marking rows owned jointly.
+pub fn read_ownership(path: impl AsRef<Path>) -> Result<Ownership> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let df = if is_csv(path) {
+        CsvReader::new(file).finish()?
+    } else {
+        ParquetReader::new(file).finish()?
+    };
+    let tables = df.column("table")?.cast(&DataType::Utf8)?;
+    let row_ids = df.column("row_id")?.cast(&DataType::UInt64)?;
+    // numeric seller names are read as numbers from csv.
+    let sellers = df.column("seller")?.cast(&DataType::Utf8)?;
+    let joint = if df.get_column_names().contains(&"joint") {
+        df.column("joint")?.cast(&DataType::Boolean)?
+    } else {
+        Series::new("joint", vec![false; df.height()])
+    };
+
+    let mut ownership = Ownership::new();
+    for (((table, row_id), seller), joint) in tables
+        .utf8()?
+        .into_iter()
+        .zip(row_ids.u64()?)
+        .zip(sellers.utf8()?)
+        .zip(joint.bool()?)
+    {
+        match (table, row_id, seller) {
+            (Some(table), Some(row_id), Some(seller)) => {
+                let owners = ownership.entry(table.to_string()).or_default();
+                owners.pairs.push((RowId(row_id), seller.to_string()));
+                if joint == Some(true) {
+                    owners.joint.insert(RowId(row_id));
+                }
+            }
+            _ => bail!("null in {}", path.display()),
+        }
+    }
+    Ok(ownership)
+}
+
+/// Write `ownership` as `(table, row_id, seller, joint)` rows, to csv if `path` ends in `.csv`
+/// and to parquet otherwise.
+pub fn write_ownership(path: impl AsRef<Path>, ownership: &Ownership) -> Result<()> {
+    let path = path.as_ref();
+    let mut tables: Vec<_> = ownership.keys().collect();
+    tables.sort();
+    let (mut table_col, mut row_id_col, mut seller_col, mut joint_col) =
+        (vec![], vec![], vec![], vec![]);
+    for table in tables {
+        let owners = &ownership[table];
+        for (row_id, seller) in &owners.pairs {
+            table_col.push(table.as_str());
+            row_id_col.push(row_id.0);
+            seller_col.push(seller.as_str());
+            joint_col.push(owners.joint.contains(row_id));
+        }
+    }
+    let df = DataFrame::new(vec![
+        Series::new("table", table_col),
+        Series::new("row_id", row_id_col),
+        Series::new("seller", seller_col),
+        Series::new("joint", joint_col),
+    ])?;
+
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    if is_csv(path) {
+        CsvWriter::new(file).finish(&df)?;
+    } else {
+        ParquetWriter::new(file).finish(&df)?;
+    }
+    Ok(())
+}
+
+/// Convert the `-index.json` and `-seller.json` files in `meta_dir` of the tables in `csv_dir`
+/// to a consolidated ownership file. The data files have no row id column, so rows are
+/// identified by their positions.
+pub fn convert_ownership(
+    csv_dir: impl AsRef<Path>,
+    meta_dir: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+) -> Result<()> {
+    let meta_dir = meta_dir.as_ref();
+    let csv_dir = csv_dir.as_ref();
+    let mut ownership = Ownership::new();
+    for (name, _, _) in Manifest::load(csv_dir)?.table_files(csv_dir)? {
+        info!("convert ownership of {}...", name);
+        let row_ids: Vec<RowId> = read_json(meta_dir.join(format!("{name}-index.json")))?;
+        let positions: HashMap<RowId, u64> = row_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, row_id)| (row_id, i as u64))
+            .collect();
+        let position = |row_id: RowId| {
+            positions
+                .get(&row_id)
+                .map(|i| RowId(*i))
+                .with_context(|| format!("row {} of {} is not in its index", row_id, name))
+        };
+        let owners = read_owners(meta_dir.join(format!("{name}-seller.json")))?;
+        let owners = Owners {
+            pairs: owners
+                .pairs
+                .into_iter()
+                .map(|(row_id, seller)| Ok((position(row_id)?, seller)))
+                .collect::<Result<_>>()?,
+            joint: owners
+                .joint
+                .into_iter()
+                .map(position)
+                .collect::<Result<_>>()?,
+        };
+        ownership.insert(name, owners);
+    }
+    write_ownership(output, &ownership)
+}
+
+/// Read the rows of table `name` from `path`, with a `_row_id` column, and their owners.
+///
+/// Owners are taken from a `_sellers` column of the data file if it has one, otherwise from
+/// `owners` (the table's part of the consolidated ownership file), otherwise from the
+/// `-index.json` and `-seller.json` files of `meta_dir`. Unless `-index.json` is used, rows
+/// are identified by the `_row_id` column of the data file if it has one, and by their
+/// positions otherwise.
+pub fn read_table(
+    name: &str,
+    path: impl AsRef<Path>,
+    options: &TableOptions,
+    meta_dir: impl AsRef<Path>,
+    owners: Option<Owners>,
+) -> Result<(DataFrame, Owners)> {
+    let meta_dir = meta_dir.as_ref();
+    let mut df = read_data_file(path, options)?;
+    let owners = match take_owners(name, &mut df, owners)? {
+        Some(owners) => owners,
+        None => {
+            let index_f = meta_dir.join(format!("{name}-index.json"));
+            let row_id: Vec<u64> = read_json(&index_f)?;
+            ensure!(
+                row_id.len() == df.height(),
+                "{}: {} row ids but {} has {} rows",
+                index_f.display(),
+                row_id.len(),
+                name,
+                df.height()
+            );
+            df.with_column(Series::new(ROW_ID_COL_NAME, row_id))?;
+            read_owners(meta_dir.join(format!("{name}-seller.json")))?
+        }
+    };
+    Ok((df, owners))
+}
+
+/// Owners of the rows of `df` from its `_sellers` column, which is dropped, or else from
+/// `owners`, setting its `_row_id` column. `None` if the table has neither.
+pub(crate) fn take_owners(
+    name: &str,
+    df: &mut DataFrame,
+    owners: Option<Owners>,
+) -> Result<Option<Owners>> {
+    let has_column = |df: &DataFrame, column: &str| df.get_column_names().contains(&column);
+    let sellers = if has_column(df, SELLERS_COL_NAME) {
+        Some(df.drop_in_place(SELLERS_COL_NAME)?)
+    } else {
+        None
+    };
+    if sellers.is_none() && owners.is_none() {
+        return Ok(None);
+    }
+
+    let row_ids: Vec<u64> = if has_column(df, ROW_ID_COL_NAME) {
+        df.column(ROW_ID_COL_NAME)?
+            .cast(&DataType::UInt64)?
+            .u64()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, row_id)| row_id.with_context(|| format!("{}: row {} has no row id", name, i)))
+            .collect::<Result<_>>()?
+    } else {
+        (0..df.height() as u64).collect()
+    };
+    let owners = match sellers {
+        Some(sellers) => {
+            let sellers = sellers.cast(&DataType::Utf8)?;
+            let mut owners = Owners::default();
+            for (i, (row_id, sellers)) in row_ids.iter().zip(sellers.utf8()?).enumerate() {
+                let sellers = sellers.unwrap_or_default();
+                let joint = sellers.contains(JOINT_SELLER_SEP);
+                ensure!(
+                    !joint || !sellers.contains(SELLER_SEP),
+                    "{}: row {} mixes `{}` and `{}` in its sellers",
+                    name,
+                    i,
+                    SELLER_SEP,
+                    JOINT_SELLER_SEP
+                );
+                if joint {
+                    owners.joint.insert(RowId(*row_id));
+                }
+                owners.pairs.extend(
+                    sellers
+                        .split(&[SELLER_SEP, JOINT_SELLER_SEP][..])
+                        .map(str::trim)
+                        .filter(|seller| !seller.is_empty())
+                        .map(|seller| (RowId(*row_id), seller.to_string())),
+                );
+            }
+            owners
+        }
+        None => owners.unwrap_or_default(),
+    };
+    df.with_column(Series::new(ROW_ID_COL_NAME, row_ids))?;
+    Ok(Some(owners))
+}
+
+/// Check the ownership metadata of the tables in `csv_dir` before loading them, reporting
+/// every problem found with its file and row: row id lists not as long as their tables,
+/// duplicate row ids, rows with no owner, owners not in `sellers.json` and owned rows not in
+/// the table.
+pub fn validate_ownership(csv_dir: impl AsRef<Path>, meta_dir: impl AsRef<Path>) -> Result<()> {
+    let meta_dir = meta_dir.as_ref();
+    let sellers_f = meta_dir.join("sellers.json");
+    let registry = if sellers_f.exists() {
+        Some(SellerRegistry::load(&sellers_f)?)
+    } else {
+        None
+    };
+    let mut ownership = match ownership_path(meta_dir) {
+        Some(path) => Some((read_ownership(&path)?, path)),
+        None => None,
+    };
+
+    let csv_dir = csv_dir.as_ref();
+    let mut errors = vec![];
+    for (name, csv_f, options) in Manifest::load(csv_dir)?.table_files(csv_dir)? {
+        info!("validate ownership of {}...", name);
+        let mut df = read_data_file(&csv_f, &options)?;
+        let source = match &ownership {
+            Some((_, path)) => path.display().to_string(),
+            None => csv_f.display().to_string(),
+        };
+        let owners = ownership
+            .as_mut()
+            .map(|(ownership, _)| ownership.remove(&name).unwrap_or_default());
+        let owners = match take_owners(&name, &mut df, owners) {
+            Ok(Some(owners)) => owners,
+            Ok(None) => {
+                errors.extend(validate_json(
+                    &name,
+                    &csv_f,
+                    df.height(),
+                    meta_dir,
+                    registry.as_ref(),
+                )?);
+                continue;
+            }
+            Err(e) => {
+                errors.push(format!("{}: {}", csv_f.display(), e));
+                continue;
+            }
+        };
+
+        let row_ids = df.column(ROW_ID_COL_NAME)?.u64()?;
+        let mut rows: HashMap<RowId, usize> = HashMap::new();
+        let mut duplicates = vec![];
+        for (i, row_id) in row_ids.into_no_null_iter().enumerate() {
+            if let Some(first) = rows.insert(RowId(row_id), i) {
+                duplicates.push(format!("row id {} (rows {} and {})", row_id, first, i));
+            }
+        }
+        report(&mut errors, &csv_f, "duplicate row ids", duplicates);
+
+        let mut owned = HashSet::new();
+        let (mut unknown_rows, mut unknown_sellers) = (vec![], vec![]);
+        for (row_id, seller) in &owners.pairs {
+            if !rows.contains_key(row_id) {
+                unknown_rows.push(format!("row id {} of {}", row_id, name));
+            }
+            if registry.as_ref().is_some_and(|r| r.id(seller).is_none()) {
+                unknown_sellers.push(format!("seller {} of row id {}", seller, row_id));
+            }
+            owned.insert(*row_id);
+        }
+        let mut unowned: Vec<_> = rows
+            .iter()
+            .filter(|(row_id, _)| !owned.contains(row_id))
+            .map(|(row_id, i)| (*i, *row_id))
+            .collect();
+        unowned.sort();
+        let unowned = unowned
+            .into_iter()
+            .map(|(i, row_id)| format!("row {} (row id {})", i, row_id))
+            .collect();
+        report(
+            &mut errors,
+            &source,
+            "owned rows not in the table",
+            unknown_rows,
+        );
+        report(
+            &mut errors,
+            &source,
+            "owners not in sellers.json",
+            unknown_sellers,
+        );
+        report(&mut errors, csv_f, "rows with no owner", unowned);
+    }
+
+    ensure!(
+        errors.is_empty(),
+        "invalid ownership metadata in {}:\n  {}",
+        meta_dir.display(),
+        errors.join("\n  ")
+    );
+    Ok(())
+}
+
+/// Problems of the `-index.json` and `-seller.json` files of table `name`, which has `height`
+/// rows.
+fn validate_json(
+    name: &str,
+    csv_f: &Path,
+    height: usize,
+    meta_dir: &Path,
+    registry: Option<&SellerRegistry>,
+) -> Result<Vec<String>> {
+    let mut errors = vec![];
+    let index_f = meta_dir.join(format!("{name}-index.json"));
+    let seller_f = meta_dir.join(format!("{name}-seller.json"));
+    let row_ids: Vec<RowId> = read_json(&index_f)?;
+    let seller = SellerFile::read(&seller_f)?;
+
+    if row_ids.len() != height {
+        errors.push(format!(
+            "{}: {} row ids but {} has {} rows",
+            index_f.display(),
+            row_ids.len(),
+            csv_f.display(),
+            height
+        ));
+    }
+    let mut rows: HashMap<RowId, usize> = HashMap::new();
+    let mut duplicates = vec![];
+    for (i, row_id) in row_ids.iter().enumerate() {
+        if let Some(first) = rows.insert(*row_id, i) {
+            duplicates.push(format!("row id {} (rows {} and {})", row_id, first, i));
+        }
+    }
+    report(&mut errors, &index_f, "duplicate row ids", duplicates);
+
+    let mut keys: Vec<_> = seller.index.keys().chain(seller.seller.keys()).collect();
+    keys.sort_by_key(|k| (k.parse::<u64>().ok(), *k));
+    keys.dedup();
+    let (mut unmatched, mut unknown_rows, mut unknown_sellers) = (vec![], vec![], vec![]);
+    let mut owned = HashSet::new();
+    for key in keys {
+        match (seller.index.get(key), seller.seller.get(key)) {
+            (Some(row_id), Some(seller_name)) => {
+                if !rows.contains_key(row_id) {
+                    unknown_rows.push(format!("key {} (row id {})", key, row_id));
+                }
+                let seller_name = seller_name.clone().into_string();
+                if registry.is_some_and(|r| r.id(&seller_name).is_none()) {
+                    unknown_sellers.push(format!("key {} (seller {})", key, seller_name));
+                }
+                owned.insert(*row_id);
+            }
+            (Some(row_id), None) => {
+                unmatched.push(format!("key {} (row id {}) has no seller", key, row_id))
+            }
+            (None, _) => unmatched.push(format!("key {} has a seller but no row id", key)),
+        }
+    }
+    for row_id in &seller.joint {
+        if !rows.contains_key(row_id) {
+            unknown_rows.push(format!("joint row id {}", row_id));
+        }
+    }
+    report(&mut errors, &seller_f, "unmatched keys", unmatched);
+    report(
+        &mut errors,
+        &seller_f,
+        &format!("row ids not in {}", index_f.display()),
+        unknown_rows,
+    );
+    report(
+        &mut errors,
+        &seller_f,
+        "owners not in sellers.json",
+        unknown_sellers,
+    );
+    let unowned = row_ids
+        .iter()
+        .enumerate()
+        .filter(|(_, row_id)| !owned.contains(row_id))
+        .map(|(i, row_id)| format!("row {} (row id {})", i, row_id))
+        .collect();
+    report(&mut errors, csv_f, "rows with no owner", unowned);
+    Ok(errors)
+}
+
+/// Report `problems` of a kind in `file`, listing the first few.
+fn report(errors: &mut Vec<String>, file: impl AsRef<Path>, kind: &str, problems: Vec<String>) {
+    const SHOWN: usize = 5;
+    if problems.is_empty() {
+        return;
+    }
+    let more = if problems.len() > SHOWN {
+        format!(", ... ({} more)", problems.len() - SHOWN)
+    } else {
+        String::new()
+    };
+    errors.push(format!(
+        "{}: {} {}: {}{}",
+        file.as_ref().display(),
+        problems.len(),
+        kind,
+        problems[..problems.len().min(SHOWN)].join(", "),
+        more
+    ));
+}
+
+fn is_csv(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "csv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alg::proposed::{proposed_scheme, ProposedOpts},
+        get_plan,
+        utils::{assert_world_sv, load_world, test_data_dir, TempDir},
+        DataSet, TupleCount,
+    };
+
+    #[test]
+    fn test_convert() {
+        polars_core::POOL.install(|| {
+            let data_dir = test_data_dir();
+            let world = load_world();
+            let tmp_dir = TempDir::new("ownership");
+            let tmp_dir = tmp_dir.path();
+
+            // the consolidated file, in both formats.
+            for file in ["ownership.parquet", "ownership.csv"] {
+                let meta_dir = tmp_dir.join(file);
+                std::fs::create_dir_all(&meta_dir).unwrap();
+                convert_ownership(
+                    data_dir.join("world"),
+                    data_dir.join("world-metadata"),
+                    meta_dir.join(file),
+                )
+                .unwrap();
+                let converted = DataSet::load(
+                    "world",
+                    data_dir.join("world"),
+                    &meta_dir,
+                    get_plan("world").unwrap(),
+                )
+                .unwrap();
+                assert_eq!(converted.sellers, world.sellers);
+                let r = proposed_scheme(&converted, &TupleCount, &ProposedOpts::default()).unwrap();
+                assert_world_sv(&r.shapley_values);
+            }
+
+            // row ids and sellers in the csv files.
+            let csv_dir = tmp_dir.join("embedded");
+            let meta_dir = tmp_dir.join("embedded-meta");
+            std::fs::create_dir_all(&csv_dir).unwrap();
+            std::fs::create_dir_all(&meta_dir).unwrap();
+            for (name, table) in &world.tables {
+                let sellers: Vec<String> = table
+                    .df
+                    .column(ROW_ID_COL_NAME)
+                    .unwrap()
+                    .u64()
+                    .unwrap()
+                    .into_iter()
+                    .map(|row_id| {
+                        let mut sellers: Vec<_> =
+                            table.seller_map[&RowId(row_id.unwrap())].iter().collect();
+                        sellers.sort();
+                        sellers
+                            .into_iter()
+                            .map(|s| world.registry.name(*s))
+                            .collect::<Vec<_>>()
+                            .join(&SELLER_SEP.to_string())
+                    })
+                    .collect();
+                let mut df = table.df.clone();
+                df.with_column(Series::new(SELLERS_COL_NAME, sellers))
+                    .unwrap();
+                let file = File::create(csv_dir.join(format!("{}.csv", name))).unwrap();
+                CsvWriter::new(file).finish(&df).unwrap();
+            }
+            let embedded =
+                DataSet::load("world", &csv_dir, &meta_dir, get_plan("world").unwrap()).unwrap();
+            assert_eq!(embedded.sellers, world.sellers);
+            for (name, table) in &embedded.tables {
+                assert_eq!(table.seller_map, world.tables[name].seller_map);
+            }
+            let r = proposed_scheme(&embedded, &TupleCount, &ProposedOpts::default()).unwrap();
+            assert_world_sv(&r.shapley_values);
+        });
+    }
+
+    #[test]
+    fn test_validate() {
+        let data_dir = test_data_dir();
+        validate_ownership(data_dir.join("world"), data_dir.join("world-metadata")).unwrap();
+
+        let tmp_dir = TempDir::new("validate");
+        let meta_dir = tmp_dir.path();
+        for table in ["city", "country", "countrylanguage"] {
+            for file in [
+                format!("{}-index.json", table),
+                format!("{}-seller.json", table),
+            ] {
+                std::fs::copy(
+                    data_dir.join("world-metadata").join(&file),
+                    meta_dir.join(&file),
+                )
+                .unwrap();
+            }
+        }
+        // drop the last row id of country, and declare all sellers but "0".
+        let index_f = meta_dir.join("country-index.json");
+        let mut row_ids: Vec<u64> =
+            serde_json::from_slice(&std::fs::read(&index_f).unwrap()).unwrap();
+        let dropped = row_ids.pop().unwrap();
+        std::fs::write(&index_f, serde_json::to_vec(&row_ids).unwrap()).unwrap();
+        let world = load_world();
+        let names: Vec<_> = world
+            .registry
+            .iter()
+            .map(|(_, info)| info.name.clone())
+            .filter(|name| name != "0")
+            .collect();
+        std::fs::write(
+            meta_dir.join("sellers.json"),
+            serde_json::to_vec(&names).unwrap(),
+        )
+        .unwrap();
+
+        let e = validate_ownership(data_dir.join("world"), meta_dir)
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains(&format!("{} row ids but", row_ids.len())));
+        assert!(e.contains("row ids not in"));
+        assert!(e.contains(&format!("row id {})", dropped)));
+        assert!(e.contains("owners not in sellers.json"));
+
+        // loading does not pair the rows with the wrong row ids either.
+        let e = DataSet::load(
+            "world",
+            data_dir.join("world"),
+            meta_dir,
+            get_plan("world").unwrap(),
+        )
+        .unwrap_err();
+        assert!(e
+            .to_string()
+            .contains(&format!("{} row ids but country has", row_ids.len())));
+    }
+}
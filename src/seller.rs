@@ -1,5 +1,10 @@
+use crate::utils::read_json;
+use anyhow::{bail, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::Path,
+};
 
 #[derive(
     Debug,
@@ -60,3 +65,265 @@ impl FromIterator<SellerId> for SellerSet {
         Self(iter.into_iter().collect())
     }
 }
+
+/// A seller as written in metadata files: its name, or a number standing for its name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(untagged)]
+pub enum SellerName {
+    Name(String),
+    Number(u64),
+}
+
+impl SellerName {
+    pub fn into_string(self) -> String {
+        match self {
+            Self::Name(name) => name,
+            Self::Number(n) => n.to_string(),
+        }
+    }
+}
+
+/// External description of a seller.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SellerInfo {
+    /// External name, e.g. a company id. Results are reported by it.
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+}
+
+impl SellerInfo {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Sellers of a dataset, mapping their external names to dense internal ids.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SellerRegistry {
+    sellers: Vec<SellerInfo>,
+    ids: HashMap<String, SellerId>,
+}
+
+impl SellerRegistry {
+    pub fn new(sellers: impl IntoIterator<Item = SellerInfo>) -> Result<Self> {
+        let mut ans = Self::default();
+        for info in sellers {
+            ans.register(info)?;
+        }
+        Ok(ans)
+    }
+
+    /// Register sellers by name only. Numeric names come first, in increasing order, so
+    /// that sellers numbered `0..n` keep their numbers as ids.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        let names: BTreeSet<(bool, Option<u64>, &str)> = names
+            .into_iter()
+            .map(|name| {
+                let n = name.parse::<u64>().ok();
+                (n.is_none(), n, name)
+            })
+            .collect();
+        let mut ans = Self::default();
+        for (_, _, name) in names {
+            ans.register(SellerInfo::new(name)).unwrap();
+        }
+        ans
+    }
+
+    /// Load from a JSON array of sellers, each a name or an object with a `name` and
+    /// optionally a `display_name` and `contact`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Name(SellerName),
+            Info(SellerInfo),
+        }
+
+        let entries: Vec<Entry> = read_json(path)?;
+        Self::new(entries.into_iter().map(|entry| match entry {
+            Entry::Name(name) => SellerInfo::new(name.into_string()),
+            Entry::Info(info) => info,
+        }))
+    }
+
+    pub fn register(&mut self, info: SellerInfo) -> Result<SellerId> {
+        let id = SellerId(self.sellers.len() as u64);
+        ensure!(
+            self.ids.insert(info.name.clone(), id).is_none(),
+            "seller {} is registered twice",
+            info.name
+        );
+        self.sellers.push(info);
+        Ok(id)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sellers.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sellers.is_empty()
+    }
+
+    #[inline]
+    pub fn id(&self, name: &str) -> Option<SellerId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Id of a seller named in a metadata file.
+    pub fn resolve(&self, name: &str) -> Result<SellerId> {
+        self.id(name)
+            .with_context(|| format!("unknown seller {}", name))
+    }
+
+    #[inline]
+    pub fn info(&self, id: SellerId) -> &SellerInfo {
+        &self.sellers[id.0 as usize]
+    }
+
+    #[inline]
+    pub fn name(&self, id: SellerId) -> &str {
+        &self.info(id).name
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SellerId, &SellerInfo)> {
+        self.sellers
+            .iter()
+            .enumerate()
+            .map(|(i, info)| (SellerId(i as u64), info))
+    }
+
+    pub fn sellers(&self) -> SellerSet {
+        (0..self.len() as u64).collect()
+    }
+}
+
+/// Priority weights of sellers for the weighted Shapley value. Sellers not listed weigh 1.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SellerWeights(pub HashMap<SellerId, f64>);
+
+impl SellerWeights {
+    /// Load from a JSON object of seller name to weight.
+    pub fn load(path: impl AsRef<Path>, registry: &SellerRegistry) -> Result<Self> {
+        let weights: HashMap<String, f64> = read_json(path)?;
+        Self::from_names(weights, registry)
+    }
+
+    /// Weights of sellers given by name, resolved in `registry`.
+    pub fn from_names(weights: HashMap<String, f64>, registry: &SellerRegistry) -> Result<Self> {
+        let mut ans = HashMap::new();
+        for (seller, w) in weights {
+            ensure!(
+                w.is_finite() && w > 0.,
+                "weight of seller {} must be positive",
+                seller
+            );
+            ans.insert(registry.resolve(&seller)?, w);
+        }
+        Ok(Self(ans))
+    }
+
+    #[inline]
+    pub fn get(&self, seller: SellerId) -> f64 {
+        self.0.get(&seller).copied().unwrap_or(1.)
+    }
+}
+
+/// A priori unions of sellers for the Owen value, e.g. sellers of the same parent company.
+/// Sellers not listed form a union of their own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SellerUnions {
+    pub names: Vec<String>,
+    union_of: HashMap<SellerId, usize>,
+    /// Names of sellers forming a union of their own, if known.
+    seller_names: HashMap<SellerId, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UnionId {
+    Named(usize),
+    Single(SellerId),
+}
+
+impl SellerUnions {
+    pub fn new(unions: impl IntoIterator<Item = (String, Vec<SellerId>)>) -> Result<Self> {
+        let mut ans = Self::default();
+        for (name, sellers) in unions {
+            for seller in sellers {
+                if let Some(other) = ans.union_of.insert(seller, ans.names.len()) {
+                    bail!(
+                        "seller {} is in both unions {} and {}",
+                        seller,
+                        ans.names[other],
+                        name
+                    );
+                }
+            }
+            ans.names.push(name);
+        }
+        Ok(ans)
+    }
+
+    /// Load from a JSON object of union name to the names of its sellers.
+    pub fn load(path: impl AsRef<Path>, registry: &SellerRegistry) -> Result<Self> {
+        let unions: BTreeMap<String, Vec<SellerName>> = read_json(path)?;
+        Self::from_names(
+            unions.into_iter().map(|(union, sellers)| {
+                let sellers = sellers.into_iter().map(SellerName::into_string).collect();
+                (union, sellers)
+            }),
+            registry,
+        )
+    }
+
+    /// Unions of sellers given by name, resolved in `registry`.
+    pub fn from_names(
+        unions: impl IntoIterator<Item = (String, Vec<String>)>,
+        registry: &SellerRegistry,
+    ) -> Result<Self> {
+        let mut ans = Self::new(
+            unions
+                .into_iter()
+                .map(|(union, sellers)| {
+                    let sellers = sellers
+                        .iter()
+                        .map(|seller| registry.resolve(seller))
+                        .collect::<Result<_>>()?;
+                    Ok((union, sellers))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        )?;
+        ans.seller_names = registry
+            .iter()
+            .map(|(id, info)| (id, info.name.clone()))
+            .collect();
+        Ok(ans)
+    }
+
+    #[inline]
+    pub fn union_id(&self, seller: SellerId) -> UnionId {
+        match self.union_of.get(&seller) {
+            Some(i) => UnionId::Named(*i),
+            None => UnionId::Single(seller),
+        }
+    }
+
+    pub fn union_name(&self, seller: SellerId) -> String {
+        match self.union_id(seller) {
+            UnionId::Named(i) => self.names[i].clone(),
+            UnionId::Single(seller) => match self.seller_names.get(&seller) {
+                Some(name) => name.clone(),
+                None => seller.to_string(),
+            },
+        }
+    }
+}
@@ -1,8 +1,14 @@
-use crate::{SellerId, SellerSet};
+use crate::{
+    ownership_path, read_ownership, read_table, utils::read_json, SellerName, SellerRegistry,
+    SellerSet,
+};
 use anyhow::{Context, Result};
 use polars::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
 
 pub const ROW_ID_COL_NAME: &str = "_row_id";
 
@@ -36,41 +42,145 @@ pub struct Table {
     pub name: String,
     pub df: DataFrame,
     pub seller_map: HashMap<RowId, SellerSet>,
+    /// Rows produced jointly by their owners, which all have to take part to supply them.
+    /// Any one owner of another row holds a full copy of it.
+    pub joint_rows: HashSet<RowId>,
+}
+
+/// The sellers of a row, and whether they own it jointly.
+#[derive(Debug, Clone, Copy)]
+pub struct RowOwners<'a> {
+    pub sellers: &'a SellerSet,
+    pub joint: bool,
+}
+
+impl RowOwners<'_> {
+    /// Whether `subset` can supply the row.
+    pub fn available(&self, subset: &SellerSet) -> bool {
+        if self.joint {
+            self.sellers.is_subset(subset)
+        } else {
+            self.sellers.intersection(subset).next().is_some()
+        }
+    }
+}
+
+/// `(row id, seller name)` pairs of the owners of the rows of a table.
+#[derive(Debug, Default, Clone)]
+pub struct Owners {
+    pub pairs: Vec<(RowId, String)>,
+    /// Rows owned jointly.
+    pub joint: HashSet<RowId>,
+}
+
+impl Owners {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.pairs.iter().map(|(_, seller)| seller.as_str())
+    }
+}
+
+impl<S: Into<String>> FromIterator<(RowId, S)> for Owners {
+    fn from_iter<I: IntoIterator<Item = (RowId, S)>>(pairs: I) -> Self {
+        Self {
+            pairs: pairs
+                .into_iter()
+                .map(|(row_id, seller)| (row_id, seller.into()))
+                .collect(),
+            joint: HashSet::new(),
+        }
+    }
 }
 
 impl Table {
+    /// Load table `name` from the data file `path`, of a format detected from its extension,
+    /// with its owners in any format read by [`read_table`], resolving them in `registry`.
     pub fn load(
         name: impl Into<String>,
-        csv_path: impl AsRef<Path>,
-        row_id_path: impl AsRef<Path>,
-        seller_path: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        meta_dir: impl AsRef<Path>,
+        registry: &SellerRegistry,
     ) -> Result<Self> {
-        let mut df = CsvReader::new(File::open(csv_path)?).finish()?;
-        let row_id: Vec<u64> = serde_json::from_reader(BufReader::new(File::open(row_id_path)?))?;
-        df.with_column(Series::new(ROW_ID_COL_NAME, row_id))?;
-
-        #[derive(Debug, Deserialize)]
-        struct Seller {
-            index: HashMap<String, RowId>,
-            seller: HashMap<String, SellerId>,
-        }
+        let name = name.into();
+        let meta_dir = meta_dir.as_ref();
+        let owners = match ownership_path(meta_dir) {
+            Some(path) => Some(read_ownership(path)?.remove(&name).unwrap_or_default()),
+            None => None,
+        };
+        let (df, owners) = read_table(&name, path, &Default::default(), meta_dir, owners)?;
+        Self::new(name, df, owners, registry)
+    }
 
-        let seller: Seller = serde_json::from_reader(BufReader::new(File::open(seller_path)?))?;
+    /// Table of `df`, which has a `_row_id` column, whose rows are owned by `owners`.
+    pub fn new(
+        name: impl Into<String>,
+        df: DataFrame,
+        owners: Owners,
+        registry: &SellerRegistry,
+    ) -> Result<Self> {
+        let name = name.into();
         let mut seller_map: HashMap<RowId, SellerSet> = HashMap::new();
-        for (i_k, i_v) in seller.index {
-            let s_v = *seller
-                .seller
-                .get(&i_k)
-                .context("failed to read -seller.json")?;
-            seller_map.entry(i_v).or_default().insert(s_v);
+        for (row_id, seller) in owners.pairs {
+            let seller = registry
+                .resolve(&seller)
+                .with_context(|| format!("failed to read sellers of {}", name))?;
+            seller_map.entry(row_id).or_default().insert(seller);
         }
 
         Ok(Self {
-            name: name.into(),
+            name,
             df,
             seller_map,
+            joint_rows: owners.joint,
         })
     }
+
+    pub fn owners(&self, row_id: RowId) -> Option<RowOwners<'_>> {
+        self.seller_map.get(&row_id).map(|sellers| RowOwners {
+            sellers,
+            joint: self.joint_rows.contains(&row_id),
+        })
+    }
+}
+
+/// A `-seller.json` file: the row id and the seller of every key. A row owned by several
+/// sellers has several keys, and is owned jointly if listed in `joint`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct SellerFile {
+    pub index: HashMap<String, RowId>,
+    pub seller: HashMap<String, SellerName>,
+    #[serde(default)]
+    pub joint: Vec<RowId>,
+}
+
+impl SellerFile {
+    pub fn read(seller_path: impl AsRef<Path>) -> Result<Self> {
+        read_json(seller_path)
+    }
+}
+
+/// Read the owners of a `-seller.json` file.
+pub fn read_owners(seller_path: impl AsRef<Path>) -> Result<Owners> {
+    let seller_path = seller_path.as_ref();
+    let mut seller = SellerFile::read(seller_path)?;
+    let pairs = seller
+        .index
+        .into_iter()
+        .map(|(i_k, i_v)| {
+            let s_v = seller.seller.remove(&i_k).with_context(|| {
+                format!(
+                    "{}: key {} (row id {}) has no seller",
+                    seller_path.display(),
+                    i_k,
+                    i_v
+                )
+            })?;
+            Ok((i_v, s_v.into_string()))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Owners {
+        pairs,
+        joint: seller.joint.into_iter().collect(),
+    })
 }
 
 #[cfg(test)]
@@ -81,14 +191,18 @@ mod tests {
     #[test]
     fn test_load() {
         let data_dir = test_data_dir();
+        let seller_f = data_dir.join("world-metadata/country-seller.json");
+        let owners = read_owners(&seller_f).unwrap();
+        let registry = SellerRegistry::from_names(owners.names());
         let country = Table::load(
             "country",
             data_dir.join("world/country.csv"),
-            data_dir.join("world-metadata/country-index.json"),
-            data_dir.join("world-metadata/country-seller.json"),
+            data_dir.join("world-metadata"),
+            &registry,
         )
         .unwrap();
-        dbg!(&country.df);
+        assert_eq!(country.df.shape().0, 239);
         assert_eq!(country.df.shape().0, country.seller_map.len());
+        assert!(country.df.column(ROW_ID_COL_NAME).is_ok());
     }
 }
@@ -0,0 +1,144 @@
+use crate::expr::Expr;
+use anyhow::{bail, Result};
+use polars::prelude::*;
+use std::fmt::Debug;
+
+/// Utility of a joined result to a buyer.
+///
+/// A utility partitions the joined tuples into units, each worth a fixed weight. A coalition
+/// earns the weight of a unit iff it can supply at least one tuple of it, so the utility stays
+/// independent across units and the proposed scheme remains exact.
+pub trait Utility: Debug + Send + Sync {
+    /// Weight of each tuple of `df`. Tuples of the same unit must have the same weight.
+    fn weights(&self, df: &DataFrame) -> Result<Vec<f64>>;
+
+    /// Columns whose distinct values form the units, or `None` if every tuple is a unit.
+    fn unit_columns(&self) -> Option<&[String]> {
+        None
+    }
+
+    /// Whether a coalition never has a lower utility than its subsets, i.e. no tuple has a
+    /// negative weight.
+    fn is_monotone(&self) -> bool {
+        false
+    }
+
+    fn evaluate(&self, df: &DataFrame) -> Result<f64> {
+        let weights = match self.unit_columns() {
+            Some(columns) => self.weights(&df.drop_duplicates(false, Some(columns))?)?,
+            None => self.weights(df)?,
+        };
+        Ok(weights.into_iter().sum())
+    }
+}
+
+/// Every joined tuple is worth 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TupleCount;
+
+impl Utility for TupleCount {
+    fn weights(&self, df: &DataFrame) -> Result<Vec<f64>> {
+        Ok(vec![1.; df.height()])
+    }
+
+    fn is_monotone(&self) -> bool {
+        true
+    }
+}
+
+/// Every joined tuple is worth the value of a numeric column, i.e. the sum of a measure.
+/// Null counts as 0.
+#[derive(Debug, Clone)]
+pub struct SumOf {
+    pub column: String,
+}
+
+impl Utility for SumOf {
+    fn weights(&self, df: &DataFrame) -> Result<Vec<f64>> {
+        Ok(df
+            .column(&self.column)?
+            .cast(&DataType::Float64)?
+            .f64()?
+            .into_iter()
+            .map(|v| v.unwrap_or_default())
+            .collect())
+    }
+}
+
+/// Every joined tuple is worth the value of an expression over its columns.
+#[derive(Debug, Clone)]
+pub struct ExprWeight {
+    pub expr: Expr,
+}
+
+impl Utility for ExprWeight {
+    fn weights(&self, df: &DataFrame) -> Result<Vec<f64>> {
+        self.expr.eval_f64(df)
+    }
+}
+
+/// Every distinct value of the key columns is worth 1.
+#[derive(Debug, Clone)]
+pub struct CountDistinct {
+    pub columns: Vec<String>,
+}
+
+impl Utility for CountDistinct {
+    fn weights(&self, df: &DataFrame) -> Result<Vec<f64>> {
+        Ok(vec![1.; df.height()])
+    }
+
+    fn unit_columns(&self) -> Option<&[String]> {
+        Some(&self.columns)
+    }
+
+    fn is_monotone(&self) -> bool {
+        true
+    }
+}
+
+/// Parse a utility from the command line.
+///
+/// Accepted values: `count`, `sum:<column>`, `expr:<expression>`, `distinct:<column>[,<column>...]`.
+pub fn parse_utility(s: &str) -> Result<Box<dyn Utility>> {
+    let (kind, arg) = s.split_once(':').unwrap_or((s, ""));
+    Ok(match (kind, arg) {
+        ("count", "") => Box::new(TupleCount),
+        ("sum", column) if !column.is_empty() => Box::new(SumOf {
+            column: column.to_string(),
+        }),
+        ("expr", expr) => Box::new(ExprWeight { expr: expr.parse()? }),
+        ("distinct", columns) if !columns.is_empty() => Box::new(CountDistinct {
+            columns: columns.split(',').map(|c| c.trim().to_string()).collect(),
+        }),
+        _ => bail!(
+            "Unknown utility {}. accepted values: [count, sum:<column>, expr:<expression>, distinct:<columns>]",
+            s
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let df = DataFrame::new(vec![
+            Series::new("k", &[1i64, 1, 2]),
+            Series::new("v", &[Some(1.5), None, Some(2.)]),
+        ])
+        .unwrap();
+        assert_eq!(parse_utility("count").unwrap().evaluate(&df).unwrap(), 3.);
+        assert_eq!(parse_utility("sum:v").unwrap().evaluate(&df).unwrap(), 3.5);
+        assert_eq!(
+            parse_utility("expr:k * 2").unwrap().evaluate(&df).unwrap(),
+            8.
+        );
+        assert_eq!(
+            parse_utility("distinct:k").unwrap().evaluate(&df).unwrap(),
+            2.
+        );
+        assert!(parse_utility("sum").is_err());
+    }
+}
@@ -1,8 +1,10 @@
+#[cfg(test)]
 use crate::SellerId;
-use anyhow::{ensure, Error, Result};
+use anyhow::{bail, ensure, Context, Error, Result};
+use serde::de::DeserializeOwned;
 #[cfg(test)]
 use std::path::PathBuf;
-use std::{cmp, collections::HashMap};
+use std::{cmp, collections::HashMap, fs::File, hash::Hash, io::BufReader, path::Path};
 use tracing_subscriber::EnvFilter;
 
 pub fn init_tracing_subscriber(default_filter: &str) -> Result<()> {
@@ -14,11 +16,63 @@ pub fn init_tracing_subscriber(default_filter: &str) -> Result<()> {
         .map_err(Error::msg)
 }
 
+/// Read a JSON file.
+pub fn read_json<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
 #[cfg(test)]
 pub fn test_data_dir() -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data")
 }
 
+/// A fresh directory under the system temp dir, removed with its contents when dropped.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            prefix,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// The world dataset of the test data, joined by its built-in plan.
+#[cfg(test)]
+pub fn load_world() -> crate::DataSet {
+    let data_dir = test_data_dir();
+    crate::DataSet::load(
+        "world",
+        data_dir.join("world"),
+        data_dir.join("world-metadata"),
+        crate::get_plan("world").unwrap(),
+    )
+    .unwrap()
+}
+
 #[cfg(test)]
 pub fn assert_world_sv(actual: &HashMap<SellerId, f64>) {
     use once_cell::sync::Lazy;
@@ -43,6 +97,28 @@ pub fn setup_rayon(num_threads: Option<usize>) -> Result<()> {
     Ok(())
 }
 
+/// Parse a size in bytes, with an optional binary unit: `1048576`, `512M` or `4GiB`.
+pub fn parse_size(s: &str) -> Result<usize> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(
+        s.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len()),
+    );
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("invalid size {}", s))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let shift = match unit.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("unknown unit of size {}", s),
+    };
+    Ok((number * (1u64 << shift) as f64) as usize)
+}
+
 #[inline]
 pub fn binom(k: usize, n: usize) -> usize {
     let k = cmp::min(k, n - k);
@@ -59,10 +135,10 @@ pub fn binom(k: usize, n: usize) -> usize {
 }
 
 #[inline]
-pub fn merge_sv(a: HashMap<SellerId, f64>, b: HashMap<SellerId, f64>) -> HashMap<SellerId, f64> {
+pub fn merge_sv<K: Eq + Hash>(a: HashMap<K, f64>, b: HashMap<K, f64>) -> HashMap<K, f64> {
     let (to_consume, mut to_mutate) = if a.len() < b.len() { (a, b) } else { (b, a) };
-    for (seller, u) in to_consume {
-        *to_mutate.entry(seller).or_default() += u;
+    for (key, u) in to_consume {
+        *to_mutate.entry(key).or_default() += u;
     }
     to_mutate
 }